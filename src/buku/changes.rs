@@ -0,0 +1,158 @@
+use super::types::{BookmarkId, Bookmarks, SavedBookmark};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// The content of every bookmark at a point in time, as a hash per ID, for
+/// determining which bookmarks have since changed.
+#[derive(Debug, Default, PartialEq)]
+pub struct Snapshot(HashMap<BookmarkId, u64>);
+
+/// The IDs of bookmarks changed between two snapshots, each sorted.
+#[derive(Debug, Default, PartialEq)]
+pub struct Changes {
+    pub added: Vec<BookmarkId>,
+    pub updated: Vec<BookmarkId>,
+    pub deleted: Vec<BookmarkId>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.deleted.is_empty()
+    }
+}
+
+fn hash_bookmark(bm: &SavedBookmark) -> u64 {
+    let mut hasher = DefaultHasher::new();
+
+    bm.url.hash(&mut hasher);
+    bm.metadata.hash(&mut hasher);
+    bm.tags.to_string().hash(&mut hasher);
+    bm.desc.hash(&mut hasher);
+    bm.flags.hash(&mut hasher);
+
+    hasher.finish()
+}
+
+impl Snapshot {
+    pub fn new(bms: &[SavedBookmark]) -> Self {
+        Snapshot(bms.iter().map(|bm| (bm.id, hash_bookmark(bm))).collect())
+    }
+
+    /// The changes that turn this snapshot into `newer`.
+    pub fn diff(&self, newer: &Snapshot) -> Changes {
+        let mut changes = Changes::default();
+
+        for (id, hash) in &newer.0 {
+            match self.0.get(id) {
+                None => changes.added.push(*id),
+                Some(old_hash) if old_hash != hash => changes.updated.push(*id),
+                Some(_) => {}
+            }
+        }
+
+        changes.deleted = self
+            .0
+            .keys()
+            .filter(|id| !newer.0.contains_key(id))
+            .copied()
+            .collect();
+
+        changes.added.sort_unstable();
+        changes.updated.sort_unstable();
+        changes.deleted.sort_unstable();
+
+        changes
+    }
+}
+
+/// A fingerprint of a set of bookmarks, in any order, that changes whenever
+/// any of them do or whenever `view`, a hash of how they're presented, does.
+/// Fingerprints are only comparable between builds of the host compiled with
+/// the same version of Rust.
+pub fn fingerprint(bms: &Bookmarks, view: u64) -> String {
+    let mut hashes = bms
+        .bookmarks
+        .iter()
+        .map(|bm| (bm.id, hash_bookmark(bm)))
+        .collect::<Vec<_>>();
+    hashes.sort_unstable();
+
+    let mut skipped = bms.skipped.iter().map(|row| row.id).collect::<Vec<_>>();
+    skipped.sort_unstable();
+
+    let mut hasher = DefaultHasher::new();
+    hashes.hash(&mut hasher);
+    skipped.hash(&mut hasher);
+    view.hash(&mut hasher);
+
+    format!("{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buku::types::Tags;
+
+    fn bm(id: BookmarkId, url: &str) -> SavedBookmark {
+        SavedBookmark {
+            id,
+            url: String::from(url),
+            metadata: String::new(),
+            tags: Tags::default(),
+            desc: String::new(),
+            flags: 0,
+        }
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let old = Snapshot::new(&[bm(1, "a"), bm(2, "b"), bm(3, "c")]);
+
+        assert!(old.diff(&old).is_empty());
+
+        let mut retagged = bm(3, "c");
+        retagged.tags = Tags::parse("rust");
+
+        assert_eq!(
+            old.diff(&Snapshot::new(&[
+                bm(1, "a"),
+                retagged,
+                bm(5, "e"),
+                bm(4, "d")
+            ])),
+            Changes {
+                added: vec![4, 5],
+                updated: vec![3],
+                deleted: vec![2],
+            },
+        );
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let bms = |bms: Vec<SavedBookmark>| Bookmarks {
+            bookmarks: bms,
+            skipped: Vec::new(),
+        };
+        let unordered = fingerprint(&bms(vec![bm(2, "b"), bm(1, "a")]), 0);
+
+        assert_eq!(
+            unordered,
+            fingerprint(&bms(vec![bm(1, "a"), bm(2, "b")]), 0)
+        );
+        assert_ne!(
+            unordered,
+            fingerprint(&bms(vec![bm(1, "a"), bm(2, "c")]), 0)
+        );
+        assert_ne!(
+            unordered,
+            fingerprint(&bms(vec![bm(1, "a"), bm(3, "b")]), 0)
+        );
+        assert_ne!(unordered, fingerprint(&bms(vec![bm(1, "a")]), 0));
+        assert_ne!(
+            unordered,
+            fingerprint(&bms(vec![bm(1, "a"), bm(2, "b")]), 1)
+        );
+    }
+}
@@ -0,0 +1,675 @@
+//! The behaviour expected of every `BukuDatabase`, as a suite of checks that
+//! any implementor can be run against.
+
+use super::database::{compose_id_moves, BatchMode, BatchResult, BukuDatabase, DbError};
+use super::tags::TagQuery;
+use super::types::{
+    BookmarkId, Bookmarks, ConflictPolicy, IdMove, ListOptions, SavedBookmark, SearchMode, SortKey,
+    TagCount, Tags, UnsavedBookmark, UrlMatch,
+};
+
+/// Run every check, each against a new and empty database from `new_db`.
+pub fn check_conformance<T, F>(new_db: F)
+where
+    T: BukuDatabase,
+    F: Fn() -> T,
+{
+    check_empty_reads(&new_db());
+    check_empty_batches(&new_db());
+    check_id_assignment(&new_db());
+    check_unknown_ids(&new_db());
+    check_duplicate_urls(&new_db());
+    check_atomic_batches(&new_db());
+    check_ordering(&new_db());
+    check_search(&new_db());
+    check_compaction(&new_db());
+    check_sync(&new_db());
+    check_tags(&new_db());
+}
+
+/// A bookmark to add, without a description or flags.
+pub fn unsaved(url: &str, metadata: &str, tags: &str) -> UnsavedBookmark {
+    UnsavedBookmark {
+        url: String::from(url),
+        metadata: String::from(metadata),
+        tags: Tags::parse(tags),
+        desc: String::new(),
+        flags: 0,
+    }
+}
+
+fn saved(id: BookmarkId, bm: UnsavedBookmark) -> SavedBookmark {
+    SavedBookmark {
+        id,
+        url: bm.url,
+        metadata: bm.metadata,
+        tags: bm.tags,
+        desc: bm.desc,
+        flags: bm.flags,
+    }
+}
+
+/// Add bookmarks with these URLs, which mustn't already be bookmarked,
+/// returning their IDs.
+fn seed<T: BukuDatabase>(db: &T, urls: &[&str]) -> Vec<BookmarkId> {
+    let bms = urls
+        .iter()
+        .map(|url| unsaved(url, "", ""))
+        .collect::<Vec<_>>();
+
+    outcomes(
+        db.add_bookmarks(&bms, ConflictPolicy::Reject, BatchMode::Atomic),
+        |added| added.bookmark.id,
+    )
+    .into_iter()
+    .map(Result::unwrap)
+    .collect()
+}
+
+/// The outcome of each write in a batch, with errors in a comparable form.
+fn outcomes<T, U, F>(res: BatchResult<T>, f: F) -> Vec<Result<U, String>>
+where
+    F: Fn(T) -> U,
+{
+    res.unwrap()
+        .into_iter()
+        .map(|res| res.map(&f).map_err(|err| format!("{:?}", err)))
+        .collect()
+}
+
+fn err<T>(err: DbError) -> Result<T, String> {
+    Err(format!("{:?}", err))
+}
+
+fn ids(bms: Bookmarks) -> Vec<BookmarkId> {
+    bms.bookmarks.into_iter().map(|bm| bm.id).collect()
+}
+
+fn urls(bms: Bookmarks) -> Vec<String> {
+    bms.bookmarks.into_iter().map(|bm| bm.url).collect()
+}
+
+fn all<T: BukuDatabase>(db: &T) -> Bookmarks {
+    db.get_all_bookmarks(&ListOptions::default()).unwrap()
+}
+
+fn check_empty_reads<T: BukuDatabase>(db: &T) {
+    let options = ListOptions::default();
+    let keywords = [String::from("a")];
+
+    assert!(ids(all(db)).is_empty());
+    assert!(ids(db.get_bookmarks_by_id(vec![1, 2]).unwrap()).is_empty());
+    assert!(ids(db.get_bookmarks_by_id(Vec::new()).unwrap()).is_empty());
+    assert!(ids(db
+        .search_bookmarks(&keywords, SearchMode::Any, &options)
+        .unwrap())
+    .is_empty());
+    assert!(ids(db
+        .get_bookmarks_by_tags(&TagQuery::parse("- a").unwrap(), &options)
+        .unwrap())
+    .is_empty());
+    assert!(db.get_tags().unwrap().tags.is_empty());
+    assert_eq!(
+        db.get_bookmarks_by_urls(&[String::from("a"), String::from("b")], &UrlMatch::Exact)
+            .unwrap()
+            .matches
+            .iter()
+            .map(Vec::len)
+            .collect::<Vec<_>>(),
+        vec![0, 0]
+    );
+    assert_eq!(db.rename_tags(&[String::from("a")], "b").unwrap(), 0);
+    assert_eq!(db.delete_tags(&[String::from("a")]).unwrap(), 0);
+}
+
+fn check_empty_batches<T: BukuDatabase>(db: &T) {
+    seed(db, &["a"]);
+    let token = db.sync_token().unwrap();
+
+    for mode in [BatchMode::Atomic, BatchMode::BestEffort] {
+        assert!(db
+            .add_bookmarks(&[], ConflictPolicy::Reject, mode)
+            .unwrap()
+            .is_empty());
+        assert!(db.update_bookmarks(&[], mode).unwrap().is_empty());
+        assert!(db.delete_bookmarks(&[], true, mode).unwrap().is_empty());
+    }
+
+    let changes = db.changes_since(&token).unwrap();
+
+    assert!(ids(changes.bookmarks).is_empty());
+    assert!(changes.deleted.is_empty());
+    assert_eq!(changes.token, token);
+    assert_eq!(ids(all(db)), vec![1]);
+}
+
+fn check_id_assignment<T: BukuDatabase>(db: &T) {
+    let added = outcomes(
+        db.add_bookmarks(
+            &[unsaved("a", "A", "x"), unsaved("b", "B", "")],
+            ConflictPolicy::Reject,
+            BatchMode::Atomic,
+        ),
+        |added| (added.bookmark.id, added.bookmark.metadata, added.existing),
+    );
+
+    assert_eq!(
+        added,
+        vec![
+            Ok((1, String::from("A"), false)),
+            Ok((2, String::from("B"), false))
+        ]
+    );
+
+    // IDs follow on from the highest, so are reused once it's deleted
+    seed(db, &["c"]);
+    db.delete_bookmarks(&[3], false, BatchMode::Atomic).unwrap();
+    assert_eq!(seed(db, &["d"]), vec![3]);
+
+    db.delete_bookmarks(&[1], false, BatchMode::Atomic).unwrap();
+    assert_eq!(seed(db, &["e"]), vec![4]);
+    assert_eq!(ids(all(db)), vec![2, 3, 4]);
+}
+
+fn check_unknown_ids<T: BukuDatabase>(db: &T) {
+    seed(db, &["a", "b", "c"]);
+
+    assert_eq!(
+        ids(db.get_bookmarks_by_id(vec![2, 9, 1, 2]).unwrap()),
+        vec![1, 2]
+    );
+    assert_eq!(
+        outcomes(
+            db.update_bookmarks(&[saved(9, unsaved("z", "", ""))], BatchMode::BestEffort),
+            |id| id
+        ),
+        vec![err(DbError::NotFound(9))]
+    );
+
+    // Repeated IDs share the outcome of their first occurrence
+    assert_eq!(
+        outcomes(
+            db.delete_bookmarks(&[9, 1, 1], false, BatchMode::BestEffort),
+            |deleted| deleted.id
+        ),
+        vec![err(DbError::NotFound(9)), Ok(1), Ok(1)]
+    );
+    assert_eq!(ids(all(db)), vec![2, 3]);
+    assert_eq!(
+        outcomes(
+            db.delete_bookmarks(&[3, 9, 3], true, BatchMode::Atomic),
+            |deleted| deleted.id
+        ),
+        vec![
+            err(DbError::RolledBack),
+            err(DbError::NotFound(9)),
+            err(DbError::RolledBack)
+        ]
+    );
+    assert_eq!(
+        outcomes(
+            db.delete_bookmarks(&[2, 2], true, BatchMode::Atomic),
+            |deleted| (deleted.id, deleted.moved)
+        ),
+        vec![Ok((2, Some(IdMove { from: 3, to: 2 }))), Ok((2, None))]
+    );
+    assert_eq!(urls(all(db)), vec!["c"]);
+}
+
+fn check_duplicate_urls<T: BukuDatabase>(db: &T) {
+    db.add_bookmarks(
+        &[unsaved("a", "A", "x")],
+        ConflictPolicy::Reject,
+        BatchMode::Atomic,
+    )
+    .unwrap();
+    let add = |bms: &[UnsavedBookmark], policy: ConflictPolicy| {
+        outcomes(
+            db.add_bookmarks(bms, policy, BatchMode::BestEffort),
+            |added| {
+                (
+                    added.bookmark.id,
+                    added.bookmark.metadata,
+                    added.bookmark.tags.to_string(),
+                    added.existing,
+                )
+            },
+        )
+    };
+    let existing =
+        |metadata: &str, tags: &str| Ok((1, String::from(metadata), String::from(tags), true));
+
+    assert_eq!(
+        add(&[unsaved("a", "B", "y")], ConflictPolicy::Reject),
+        vec![err(DbError::DuplicateUrl(1))]
+    );
+    assert_eq!(
+        add(&[unsaved("a", "B", "y")], ConflictPolicy::Skip),
+        vec![existing("A", ",x,")]
+    );
+    assert_eq!(
+        add(&[unsaved("a", "B", "y")], ConflictPolicy::MergeTags),
+        vec![existing("A", ",x,y,")]
+    );
+    assert_eq!(
+        add(&[unsaved("a", "B", "y")], ConflictPolicy::Overwrite),
+        vec![existing("B", ",y,")]
+    );
+
+    // URLs are compared exactly, including within the same batch
+    assert_eq!(
+        add(
+            &[unsaved("A", "", ""), unsaved("A", "", "")],
+            ConflictPolicy::Reject
+        ),
+        vec![
+            Ok((2, String::new(), String::from(","), false)),
+            err(DbError::DuplicateUrl(2))
+        ]
+    );
+
+    assert_eq!(
+        outcomes(
+            db.update_bookmarks(
+                &[
+                    saved(2, unsaved("a", "", "")),
+                    saved(1, unsaved("a", "C", ""))
+                ],
+                BatchMode::BestEffort
+            ),
+            |id| id
+        ),
+        vec![err(DbError::DuplicateUrl(1)), Ok(1)]
+    );
+    assert_eq!(
+        all(db)
+            .bookmarks
+            .into_iter()
+            .map(|bm| (bm.id, bm.url, bm.metadata))
+            .collect::<Vec<_>>(),
+        vec![
+            (1, String::from("a"), String::from("C")),
+            (2, String::from("A"), String::new())
+        ]
+    );
+}
+
+fn check_atomic_batches<T: BukuDatabase>(db: &T) {
+    seed(db, &["a"]);
+    let batch = [
+        unsaved("b", "", ""),
+        unsaved("a", "", ""),
+        unsaved("c", "", ""),
+    ];
+
+    assert_eq!(
+        outcomes(
+            db.add_bookmarks(&batch, ConflictPolicy::Reject, BatchMode::Atomic),
+            |added| added.bookmark.id
+        ),
+        vec![
+            err(DbError::RolledBack),
+            err(DbError::DuplicateUrl(1)),
+            err(DbError::RolledBack)
+        ]
+    );
+    assert_eq!(ids(all(db)), vec![1]);
+
+    assert_eq!(
+        outcomes(
+            db.add_bookmarks(&batch, ConflictPolicy::Reject, BatchMode::BestEffort),
+            |added| added.bookmark.id
+        ),
+        vec![Ok(2), err(DbError::DuplicateUrl(1)), Ok(3)]
+    );
+
+    assert_eq!(
+        outcomes(
+            db.update_bookmarks(
+                &[
+                    saved(1, unsaved("a", "A", "")),
+                    saved(9, unsaved("z", "", ""))
+                ],
+                BatchMode::Atomic
+            ),
+            |id| id
+        ),
+        vec![err(DbError::RolledBack), err(DbError::NotFound(9))]
+    );
+    assert_eq!(
+        outcomes(
+            db.delete_bookmarks(&[3, 9], true, BatchMode::Atomic),
+            |deleted| deleted.id
+        ),
+        vec![err(DbError::RolledBack), err(DbError::NotFound(9))]
+    );
+    assert_eq!(
+        all(db)
+            .bookmarks
+            .into_iter()
+            .map(|bm| (bm.id, bm.metadata))
+            .collect::<Vec<_>>(),
+        vec![(1, String::new()), (2, String::new()), (3, String::new())]
+    );
+}
+
+fn check_ordering<T: BukuDatabase>(db: &T) {
+    db.add_bookmarks(
+        &[
+            unsaved("https://b.example.com/", "b", "x,y"),
+            unsaved("https://a.example.com/", "C", ""),
+            unsaved("https://c.example.com/", "B", "x"),
+            unsaved("https://a.example.com/2", "a", "x,y,z"),
+        ],
+        ConflictPolicy::Reject,
+        BatchMode::Atomic,
+    )
+    .unwrap();
+    let list = |sort: SortKey, descending: bool, limit: Option<usize>| {
+        ids(db
+            .get_all_bookmarks(&ListOptions {
+                sort,
+                descending,
+                limit,
+            })
+            .unwrap())
+    };
+
+    assert_eq!(list(SortKey::Id, false, None), vec![1, 2, 3, 4]);
+    assert_eq!(list(SortKey::Id, true, Some(2)), vec![4, 3]);
+    assert_eq!(list(SortKey::Id, false, Some(usize::MAX)), vec![1, 2, 3, 4]);
+    assert!(list(SortKey::Title, false, Some(0)).is_empty());
+    // Ties are broken by ID in the same direction
+    assert_eq!(list(SortKey::Title, false, None), vec![4, 1, 3, 2]);
+    assert_eq!(list(SortKey::Title, true, None), vec![2, 3, 1, 4]);
+    assert_eq!(list(SortKey::Url, false, Some(3)), vec![2, 4, 1]);
+    assert_eq!(list(SortKey::Domain, false, None), vec![2, 4, 1, 3]);
+    assert_eq!(list(SortKey::Domain, true, Some(3)), vec![3, 1, 4]);
+    assert_eq!(list(SortKey::TagCount, true, None), vec![4, 1, 3, 2]);
+
+    assert_eq!(
+        ids(db
+            .search_bookmarks(
+                &[String::from("A.EXAMPLE"), String::from("y")],
+                SearchMode::Any,
+                &ListOptions::default()
+            )
+            .unwrap()),
+        vec![1, 2, 4]
+    );
+    assert_eq!(
+        ids(db
+            .get_bookmarks_by_host("example.com", true, &ListOptions::default())
+            .unwrap()),
+        vec![1, 2, 3, 4]
+    );
+    assert_eq!(
+        ids(db
+            .get_bookmarks_by_host(
+                "example.com",
+                true,
+                &ListOptions {
+                    sort: SortKey::Domain,
+                    descending: true,
+                    limit: Some(2),
+                }
+            )
+            .unwrap()),
+        vec![3, 1]
+    );
+
+    let prefixed = |prefix: &str, options: &ListOptions| {
+        ids(db.get_bookmarks_by_url_prefix(prefix, options).unwrap())
+    };
+
+    assert_eq!(
+        prefixed("https://a.example.com/", &ListOptions::default()),
+        vec![2, 4]
+    );
+    assert_eq!(
+        prefixed(
+            "https://a.example.com/",
+            &ListOptions {
+                sort: SortKey::Url,
+                descending: true,
+                limit: Some(1),
+            }
+        ),
+        vec![4]
+    );
+    assert_eq!(prefixed("", &ListOptions::default()), vec![1, 2, 3, 4]);
+    // Prefixes are matched case-sensitively and literally
+    for prefix in &[
+        "HTTPS://a.example.com/",
+        "https://_.example.com/",
+        "https://a.example.com/%",
+        "%example",
+    ] {
+        assert!(prefixed(prefix, &ListOptions::default()).is_empty());
+    }
+
+    let matches = db
+        .get_bookmarks_by_urls(
+            &[
+                String::from("https://c.example.com/"),
+                String::from("https://d.example.com/"),
+                String::from("https://b.example.com/"),
+            ],
+            &UrlMatch::Exact,
+        )
+        .unwrap()
+        .matches
+        .into_iter()
+        .map(|bms| bms.into_iter().map(|bm| bm.id).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+
+    assert_eq!(matches, vec![vec![3], vec![], vec![1]]);
+    assert_eq!(
+        db.get_tags().unwrap().tags,
+        vec![
+            TagCount {
+                tag: String::from("x"),
+                count: 3
+            },
+            TagCount {
+                tag: String::from("y"),
+                count: 2
+            },
+            TagCount {
+                tag: String::from("z"),
+                count: 1
+            },
+        ]
+    );
+}
+
+fn check_search<T: BukuDatabase>(db: &T) {
+    db.add_bookmarks(
+        &[
+            unsaved("https://a.example.com/100%", "Percent", ""),
+            unsaved("https://b.example.com/a_b", "Underscore", "web"),
+            unsaved("https://c.example.com/", "Back\\slash", "rust,web"),
+            unsaved("https://d.example.com/1000/axb", "Decks", ""),
+        ],
+        ConflictPolicy::Reject,
+        BatchMode::Atomic,
+    )
+    .unwrap();
+    let search = |keywords: &[&str], mode: SearchMode| {
+        ids(db
+            .search_bookmarks(
+                &keywords
+                    .iter()
+                    .map(|kw| String::from(*kw))
+                    .collect::<Vec<_>>(),
+                mode,
+                &ListOptions::default(),
+            )
+            .unwrap())
+    };
+
+    assert_eq!(search(&["EXAMPLE", "web"], SearchMode::All), vec![2, 3]);
+    assert_eq!(search(&["web", "rust"], SearchMode::All), vec![3]);
+    assert_eq!(search(&["rust", "PERCENT"], SearchMode::Any), vec![1, 3]);
+    assert!(search(&[], SearchMode::All).is_empty());
+
+    // Keywords are matched literally, not as `LIKE` patterns
+    assert_eq!(search(&["100%"], SearchMode::Any), vec![1]);
+    assert_eq!(search(&["a_b"], SearchMode::Any), vec![2]);
+    assert_eq!(search(&["k\\s"], SearchMode::Any), vec![3]);
+    assert!(search(&["%", "_"], SearchMode::All).is_empty());
+}
+
+fn check_compaction<T: BukuDatabase>(db: &T) {
+    seed(db, &["a", "b", "c", "d", "e"]);
+
+    // Deletions are applied from the highest ID down, but reported in the
+    // order requested
+    let deleted = db
+        .delete_bookmarks(&[2, 4], true, BatchMode::Atomic)
+        .unwrap();
+
+    assert_eq!(
+        deleted
+            .iter()
+            .map(|res| res.as_ref().map(|bm| (bm.id, bm.moved)).unwrap())
+            .collect::<Vec<_>>(),
+        vec![
+            (2, Some(IdMove { from: 4, to: 2 })),
+            (4, Some(IdMove { from: 5, to: 4 }))
+        ]
+    );
+    assert_eq!(compose_id_moves(&deleted), vec![IdMove { from: 5, to: 2 }]);
+    assert_eq!(urls(all(db)), vec!["a", "e", "c"]);
+
+    // Deleting the highest ID leaves nothing to move
+    assert_eq!(
+        outcomes(
+            db.delete_bookmarks(&[3], true, BatchMode::Atomic),
+            |deleted| deleted.moved
+        ),
+        vec![Ok(None)]
+    );
+    assert_eq!(urls(all(db)), vec!["a", "e"]);
+}
+
+fn check_sync<T: BukuDatabase>(db: &T) {
+    assert!(matches!(
+        db.changes_since("not a token"),
+        Err(DbError::SyncTokenExpired)
+    ));
+
+    seed(db, &["a", "b", "c"]);
+    let token = db.sync_token().unwrap();
+
+    db.add_bookmarks(
+        &[unsaved("d", "", "")],
+        ConflictPolicy::Reject,
+        BatchMode::Atomic,
+    )
+    .unwrap();
+    db.update_bookmarks(&[saved(2, unsaved("b", "B", ""))], BatchMode::Atomic)
+        .unwrap();
+    // Moves the new bookmark into the freed ID
+    db.delete_bookmarks(&[3], true, BatchMode::Atomic).unwrap();
+
+    let changes = db.changes_since(&token).unwrap();
+    let mut changed = ids(changes.bookmarks);
+    changed.sort_unstable();
+
+    assert_eq!(changed, vec![2, 3]);
+    assert_eq!(changes.deleted, vec![4]);
+    assert_ne!(changes.token, token);
+
+    let unchanged = db.changes_since(&changes.token).unwrap();
+
+    assert!(ids(unchanged.bookmarks).is_empty());
+    assert!(unchanged.deleted.is_empty());
+    assert_eq!(unchanged.token, changes.token);
+
+    let future = changes.token.parse::<i64>().unwrap() + 1;
+
+    assert!(matches!(
+        db.changes_since(&future.to_string()),
+        Err(DbError::SyncTokenExpired)
+    ));
+}
+
+fn check_tags<T: BukuDatabase>(db: &T) {
+    db.add_bookmarks(
+        &[
+            unsaved("a", "", "rust,go"),
+            unsaved("b", "", "go,web"),
+            unsaved("c", "", "web"),
+            unsaved("d", "", ", Rust ,cli"),
+        ],
+        ConflictPolicy::Reject,
+        BatchMode::Atomic,
+    )
+    .unwrap();
+    let tags = |db: &T| {
+        all(db)
+            .bookmarks
+            .into_iter()
+            .map(|bm| bm.tags.to_string())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        db.rename_tags(&[String::from("Rust"), String::from("go")], "lang")
+            .unwrap(),
+        3
+    );
+    assert_eq!(
+        tags(db),
+        vec![",lang,", ",lang,web,", ",web,", ",cli,lang,"]
+    );
+    assert_eq!(
+        ids(db
+            .get_bookmarks_by_tags(
+                &TagQuery::parse("lang - web").unwrap(),
+                &ListOptions::default()
+            )
+            .unwrap()),
+        vec![1, 4]
+    );
+    assert_eq!(db.delete_tags(&[String::from("web")]).unwrap(), 2);
+    assert_eq!(tags(db), vec![",lang,", ",lang,", ",", ",cli,lang,"]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buku::database::{LockConfig, SqliteDatabase};
+    use crate::buku::memory::InMemoryDatabase;
+    use std::cell::Cell;
+    use std::fs::remove_file;
+
+    #[test]
+    fn test_sqlite_conformance() {
+        let created = Cell::new(0);
+        let path = |n: usize| {
+            std::env::temp_dir().join(format!(
+                "bukubrow-conformance-{}-{}.db",
+                std::process::id(),
+                n
+            ))
+        };
+
+        check_conformance(|| {
+            let path = path(created.replace(created.get() + 1));
+            let _ = remove_file(&path);
+
+            SqliteDatabase::create(&path, LockConfig::default()).unwrap()
+        });
+
+        for n in 0..created.get() {
+            remove_file(path(n)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_in_memory_conformance() {
+        check_conformance(InMemoryDatabase::default);
+    }
+}
@@ -1,59 +1,731 @@
-use super::types::{BookmarkId, SavedBookmark, UnsavedBookmark};
-pub use rusqlite::Error as DbError;
-use rusqlite::{types::ToSql, Connection, Row, NO_PARAMS};
+use super::sync;
+use super::tags::TagQuery;
+use super::types::{
+    BookmarkId, Bookmarks, ConflictPolicy, IdMove, ListOptions, SavedBookmark, SearchMode,
+    SkippedRow, SortKey, TagCount, TagCounts, Tags, UnsavedBookmark, UrlMatch, UrlMatches,
+};
+use super::urls::{is_on_host, normalise_url, url_host};
+use crate::config::{
+    BUSY_TIMEOUT_ENV, DEFAULT_BUSY_TIMEOUT_MS, DEFAULT_LOCK_RETRIES, LOCK_RETRIES_ENV,
+};
+use rusqlite::{
+    types::ToSql, Connection, ErrorCode, OptionalExtension, Row, Transaction, TransactionBehavior,
+    NO_PARAMS,
+};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::env::var;
+use std::fmt;
 use std::path::Path;
+use std::thread::sleep;
+use std::time::Duration;
 
+/// Storage with Buku's semantics. Every implementor must pass the checks in
+/// `conformance`.
 pub trait BukuDatabase {
-    fn get_all_bookmarks(&self) -> Result<Vec<SavedBookmark>, DbError>;
-    fn get_bookmarks_by_id(&self, ids: Vec<BookmarkId>) -> Result<Vec<SavedBookmark>, DbError>;
-    fn add_bookmarks(&self, bms: &[UnsavedBookmark]) -> Result<Vec<usize>, DbError>;
-    fn update_bookmarks(&self, bms: &[SavedBookmark]) -> Result<Vec<usize>, DbError>;
-    fn delete_bookmarks(&self, bm_id: &[BookmarkId]) -> Result<Vec<usize>, DbError>;
+    /// Bookmarks are listed in order of ID unless `options` state otherwise.
+    fn get_all_bookmarks(&self, options: &ListOptions) -> Result<Bookmarks, DbError>;
+    /// Bookmarks with any of `ids`, each once and in order of ID. Unknown IDs
+    /// are ignored.
+    fn get_bookmarks_by_id(&self, ids: Vec<BookmarkId>) -> Result<Bookmarks, DbError>;
+    /// Case-insensitive substring search over the URL, title, tags and
+    /// description of each bookmark. No keywords matches no bookmarks.
+    fn search_bookmarks(
+        &self,
+        keywords: &[String],
+        mode: SearchMode,
+        options: &ListOptions,
+    ) -> Result<Bookmarks, DbError>;
+    fn get_bookmarks_by_tags(
+        &self,
+        query: &TagQuery,
+        options: &ListOptions,
+    ) -> Result<Bookmarks, DbError>;
+    /// Bookmarks on `host`, and optionally on any of its subdomains.
+    fn get_bookmarks_by_host(
+        &self,
+        host: &str,
+        include_subdomains: bool,
+        options: &ListOptions,
+    ) -> Result<Bookmarks, DbError>;
+    /// Bookmarks whose URLs begin with `prefix`, case-sensitively.
+    fn get_bookmarks_by_url_prefix(
+        &self,
+        prefix: &str,
+        options: &ListOptions,
+    ) -> Result<Bookmarks, DbError>;
+    /// The bookmarks matching each of `urls`, in the same order.
+    fn get_bookmarks_by_urls(
+        &self,
+        urls: &[String],
+        matching: &UrlMatch,
+    ) -> Result<UrlMatches, DbError>;
+    /// A number that changes whenever another connection, such as the Buku
+    /// CLI, commits a change to the database.
+    fn data_version(&self) -> Result<i64, DbError>;
+    /// A token for the current state of the bookmarks, to later be passed to
+    /// `changes_since`. Tracking of changes begins with the first token.
+    fn sync_token(&self) -> Result<String, DbError>;
+    /// The bookmarks added, updated or deleted since `token` was issued, in
+    /// any order, and a token for the current state.
+    fn changes_since(&self, token: &str) -> Result<SyncChanges, DbError>;
+    /// Every distinct tag in use, sorted alphabetically, with the number of
+    /// bookmarks it's applied to.
+    fn get_tags(&self) -> Result<TagCounts, DbError>;
+    /// Rename one or more tags to a single tag across every bookmark, merging
+    /// them where several are supplied. Returns the number of bookmarks
+    /// updated.
+    fn rename_tags(&self, from: &[String], to: &str) -> Result<usize, DbError>;
+    /// Remove tags from every bookmark. Returns the number of bookmarks
+    /// updated.
+    fn delete_tags(&self, tags: &[String]) -> Result<usize, DbError>;
+    /// Returns each bookmark as saved, including its newly assigned ID, which
+    /// follows on from the highest. New bookmarks whose URLs are already
+    /// bookmarked, including earlier in the same batch, are handled per
+    /// `policy`. URLs are compared exactly.
+    fn add_bookmarks(
+        &self,
+        bms: &[UnsavedBookmark],
+        policy: ConflictPolicy,
+        mode: BatchMode,
+    ) -> BatchResult<AddedBookmark>;
+    /// Returns the ID of each bookmark updated. Bookmarks that don't exist fail
+    /// with `NotFound`, and those given another bookmark's URL with
+    /// `DuplicateUrl`.
+    fn update_bookmarks(&self, bms: &[SavedBookmark], mode: BatchMode) -> BatchResult<BookmarkId>;
+    /// Returns each bookmark deleted. If `compact` is set then, as per Buku,
+    /// the bookmark with the highest ID is moved into each freed ID so that
+    /// IDs remain contiguous. Unknown IDs fail with `NotFound`, and repeated
+    /// IDs share the outcome of their first occurrence.
+    fn delete_bookmarks(
+        &self,
+        bm_ids: &[BookmarkId],
+        compact: bool,
+        mode: BatchMode,
+    ) -> BatchResult<DeletedBookmark>;
 }
 
+#[derive(Debug)]
+pub enum DbError {
+    /// No bookmark exists with this ID.
+    NotFound(BookmarkId),
+    /// Another bookmark, with this ID, already has this URL.
+    DuplicateUrl(BookmarkId),
+    /// Any other constraint, such as a bookmark's URL being missing.
+    ConstraintViolation,
+    /// Another connection, for example the Buku CLI, is holding a lock.
+    Locked,
+    /// The database is not one that Buku could use, or has been modified
+    /// beyond recognition.
+    IncompatibleSchema(String),
+    /// The write succeeded, but was undone because another write in the same
+    /// atomic batch failed.
+    RolledBack,
+    /// The sync token is too old, or is otherwise unrecognised, such that a
+    /// full sync is needed.
+    SyncTokenExpired,
+    Sqlite(rusqlite::Error),
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DbError::NotFound(id) => write!(f, "No bookmark exists with ID {}.", id),
+            DbError::DuplicateUrl(id) => {
+                write!(f, "Bookmark with ID {} already has this URL.", id)
+            }
+            DbError::ConstraintViolation => write!(f, "Bookmark violates a database constraint."),
+            DbError::Locked => write!(f, "Buku database is locked."),
+            DbError::RolledBack => write!(f, "Rolled back as another change failed."),
+            DbError::SyncTokenExpired => write!(f, "Sync token has expired."),
+            DbError::IncompatibleSchema(reason) => {
+                write!(f, "Incompatible Buku database: {}", reason)
+            }
+            DbError::Sqlite(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for DbError {
+    fn from(err: rusqlite::Error) -> Self {
+        match &err {
+            rusqlite::Error::SqliteFailure(failure, _) => match failure.code {
+                ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked => DbError::Locked,
+                ErrorCode::NotADatabase => {
+                    DbError::IncompatibleSchema(String::from("Not a SQLite database."))
+                }
+                // Duplicate URLs are checked for ahead of writes so that the
+                // conflicting bookmark can be reported
+                ErrorCode::ConstraintViolation => DbError::ConstraintViolation,
+                _ => DbError::Sqlite(err),
+            },
+            _ => DbError::Sqlite(err),
+        }
+    }
+}
+
+/// Bookmarks changed since a sync token was issued.
+pub struct SyncChanges {
+    /// Bookmarks added or updated.
+    pub bookmarks: Bookmarks,
+    /// IDs of bookmarks deleted, which may include bookmarks added and then
+    /// deleted in the interim.
+    pub deleted: Vec<BookmarkId>,
+    pub token: String,
+}
+
+/// The outcome of each write in a batch, in the order they were supplied. The
+/// outer error denotes the batch as a whole having failed. An empty batch
+/// succeeds without writing anything.
+pub type BatchResult<T> = Result<Vec<Result<T, DbError>>, DbError>;
+
+/// A bookmark saved by `add_bookmarks`.
+pub struct AddedBookmark {
+    pub bookmark: SavedBookmark,
+    /// Whether the URL was already bookmarked, in which case `bookmark` is the
+    /// existing bookmark after the conflict policy was applied.
+    pub existing: bool,
+}
+
+/// A bookmark deleted by `delete_bookmarks`.
+pub struct DeletedBookmark {
+    pub id: BookmarkId,
+    /// The bookmark moved into the freed ID by compaction, if any.
+    pub moved: Option<IdMove>,
+}
+
+/// The overall change of IDs caused by a batch of deletions. A bookmark can
+/// be moved more than once, in which case its moves are composed.
+pub fn compose_id_moves(deleted: &[Result<DeletedBookmark, DbError>]) -> Vec<IdMove> {
+    let mut deleted: Vec<&DeletedBookmark> =
+        deleted.iter().filter_map(|res| res.as_ref().ok()).collect();
+    // Deletions are applied from the highest ID down
+    deleted.sort_unstable_by_key(|bm| Reverse(bm.id));
+
+    let mut moves: Vec<IdMove> = Vec::new();
+
+    for mv in deleted.into_iter().filter_map(|bm| bm.moved) {
+        match moves.iter_mut().find(|prev| prev.to == mv.from) {
+            Some(prev) => prev.to = mv.to,
+            None => moves.push(mv),
+        }
+    }
+
+    moves
+}
+
+/// Delete bookmarks by ID from the highest down, reporting the outcome in the
+/// order requested. Compaction only ever moves the bookmark with the highest
+/// ID, so this ensures that it never moves a bookmark that's yet to be
+/// deleted.
+///
+/// Each ID is deleted once. Any repeats are no-ops that share the outcome of
+/// the first, so that repeating an ID never fails an otherwise successful
+/// batch.
+pub fn delete_highest_first<F>(bm_ids: &[BookmarkId], delete: F) -> BatchResult<DeletedBookmark>
+where
+    F: FnOnce(&[BookmarkId]) -> BatchResult<DeletedBookmark>,
+{
+    let mut ids = bm_ids.to_vec();
+    ids.sort_unstable_by(|a, b| b.cmp(a));
+    ids.dedup();
+
+    let mut results = delete(&ids)?
+        .into_iter()
+        .zip(ids)
+        .map(|(res, id)| (id, res))
+        .collect::<HashMap<_, _>>();
+    let mut firsts: HashMap<BookmarkId, usize> = HashMap::new();
+    let mut ordered: Vec<Result<DeletedBookmark, DbError>> = Vec::with_capacity(bm_ids.len());
+
+    for id in bm_ids {
+        let res = match (results.remove(id), firsts.get(id)) {
+            (Some(res), _) => {
+                firsts.insert(*id, ordered.len());
+                res
+            }
+            (None, Some(first)) => match &ordered[*first] {
+                Ok(_) => Ok(DeletedBookmark {
+                    id: *id,
+                    moved: None,
+                }),
+                Err(DbError::RolledBack) => Err(DbError::RolledBack),
+                Err(_) => Err(DbError::NotFound(*id)),
+            },
+            // Every ID is either deleted or a repeat
+            (None, None) => Err(DbError::NotFound(*id)),
+        };
+
+        ordered.push(res);
+    }
+
+    Ok(ordered)
+}
+
+/// Sort bookmarks per `sort`, stably so that ties remain in the order given.
+pub fn sort_bookmarks(bms: &mut [SavedBookmark], sort: SortKey, descending: bool) {
+    fn sort_by_key<K, F>(bms: &mut [SavedBookmark], descending: bool, key: F)
+    where
+        K: Ord,
+        F: Fn(&SavedBookmark) -> K,
+    {
+        if descending {
+            bms.sort_by_cached_key(|bm| Reverse(key(bm)));
+        } else {
+            bms.sort_by_cached_key(key);
+        }
+    }
+
+    match sort {
+        SortKey::Id => sort_by_key(bms, descending, |bm| bm.id),
+        // As SQLite's `NOCASE` collation, which only folds ASCII
+        SortKey::Title => sort_by_key(bms, descending, |bm| bm.metadata.to_ascii_lowercase()),
+        SortKey::Url => sort_by_key(bms, descending, |bm| bm.url.clone()),
+        SortKey::Domain => sort_by_key(bms, descending, |bm| url_host(&bm.url)),
+        SortKey::TagCount => sort_by_key(bms, descending, |bm| bm.tags.iter().count()),
+    }
+}
+
+/// How a batch of writes behaves should any of them fail.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BatchMode {
+    /// Apply every write or, if any fail, none of them.
+    Atomic,
+    /// Apply every write that succeeds.
+    BestEffort,
+}
+
+/// How long to wait for, and how often to retry, when another connection such
+/// as the Buku CLI holds a lock on the database.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LockConfig {
+    pub busy_timeout: Duration,
+    /// The number of times to retry a write that's still locked out after
+    /// the busy timeout.
+    pub retries: u32,
+}
+
+impl Default for LockConfig {
+    fn default() -> Self {
+        LockConfig {
+            busy_timeout: Duration::from_millis(DEFAULT_BUSY_TIMEOUT_MS),
+            retries: DEFAULT_LOCK_RETRIES,
+        }
+    }
+}
+
+impl LockConfig {
+    /// The default configuration, overridden by any environment variables.
+    pub fn from_env() -> Self {
+        let default = LockConfig::default();
+
+        LockConfig {
+            busy_timeout: var(BUSY_TIMEOUT_ENV)
+                .ok()
+                .and_then(|ms| ms.parse().ok())
+                .map_or(default.busy_timeout, Duration::from_millis),
+            retries: var(LOCK_RETRIES_ENV)
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(default.retries),
+        }
+    }
+}
+
+/// The delay before retrying a locked write, multiplied by the attempt.
+const LOCK_RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
 pub struct SqliteDatabase {
     connection: Connection,
+    lock_config: LockConfig,
 }
 
 impl SqliteDatabase {
     // Initiate connection to Sqlite database at specified path
-    pub fn new(path: &Path) -> Result<Self, DbError> {
-        let connection = Connection::open(&path)?;
+    pub fn new(path: &Path, lock_config: LockConfig) -> Result<Self, DbError> {
+        let connection = Connection::open(path)?;
+        connection.busy_timeout(lock_config.busy_timeout)?;
+        validate_schema(&connection)?;
+
+        let instance = SqliteDatabase {
+            connection,
+            lock_config,
+        };
+
+        Ok(instance)
+    }
+
+    /// Create a database with Buku's schema at the specified path, or open it
+    /// should it already exist.
+    pub fn create(path: &Path, lock_config: LockConfig) -> Result<Self, DbError> {
+        let connection = Connection::open(path)?;
+        connection.busy_timeout(lock_config.busy_timeout)?;
 
-        let instance = SqliteDatabase { connection };
+        // Nota bene that this must exactly match the schema created by Buku's
+        // internal `initdb` function.
+        connection.execute_batch(
+            "CREATE TABLE if not exists bookmarks (id integer PRIMARY KEY, URL text NOT NULL UNIQUE, metadata text default '', tags text default ',', desc text default '', flags integer default 0);",
+        )?;
+        validate_schema(&connection)?;
+
+        let instance = SqliteDatabase {
+            connection,
+            lock_config,
+        };
 
         Ok(instance)
     }
+
+    /// Begin a transaction that takes the write lock immediately. Deferring
+    /// this until the first write risks a deadlock with another writer, which
+    /// SQLite resolves by failing without waiting out the busy timeout.
+    ///
+    /// Transactions are never held open between requests, so as not to lock
+    /// out Buku.
+    fn immediate_transaction(&self) -> Result<Transaction<'_>, rusqlite::Error> {
+        Transaction::new_unchecked(&self.connection, TransactionBehavior::Immediate)
+    }
+
+    /// Retry an operation for as long as it's locked out, per the lock
+    /// configuration. The operation must be safe to repeat, as is a
+    /// transaction that was rolled back.
+    fn retry_locked<T, F>(&self, op: F) -> Result<T, DbError>
+    where
+        F: Fn() -> Result<T, DbError>,
+    {
+        let mut attempt = 0;
+
+        loop {
+            match op() {
+                Err(DbError::Locked) if attempt < self.lock_config.retries => {
+                    attempt += 1;
+                    sleep(LOCK_RETRY_BACKOFF * attempt);
+                }
+                res => break res,
+            }
+        }
+    }
+
+    /// Read the bookmarks matching the SQL `filter`, and `keep` if supplied,
+    /// sorted and limited per `options`.
+    fn list_bookmarks<P>(
+        &self,
+        filter: &str,
+        params: P,
+        keep: Option<&dyn Fn(&SavedBookmark) -> bool>,
+        options: &ListOptions,
+    ) -> Result<Bookmarks, DbError>
+    where
+        P: IntoIterator,
+        P::Item: ToSql,
+    {
+        let direction = if options.descending { "DESC" } else { "ASC" };
+        let query = format!(
+            "SELECT {cols} FROM bookmarks WHERE {filter} ORDER BY {sort} {dir}, id {dir}",
+            cols = BOOKMARK_COLUMNS,
+            filter = filter,
+            sort = sort_sql(options.sort).unwrap_or("id"),
+            dir = direction,
+        );
+        let sorted = sort_sql(options.sort).is_some();
+
+        let mut stmt = self.connection.prepare(&query)?;
+        let mut bookmarks = Bookmarks::default();
+
+        // The limit is of bookmarks listed, so rows can't be limited in SQL
+        // without also counting those that are skipped or filtered out
+        for row in stmt.query_map(params, map_db_row)? {
+            // Rows already in order can stop being read once there are enough
+            if sorted && options.limit == Some(bookmarks.bookmarks.len()) {
+                break;
+            }
+
+            match (row?, keep) {
+                (Ok(bm), Some(keep)) if !keep(&bm) => {}
+                (Ok(bm), _) => bookmarks.bookmarks.push(bm),
+                (Err(skipped), _) => bookmarks.skipped.push(skipped),
+            }
+        }
+
+        // Sorting is stable, leaving ties in order of ID
+        if !sorted {
+            sort_bookmarks(&mut bookmarks.bookmarks, options.sort, options.descending);
+        }
+
+        if let Some(limit) = options.limit {
+            bookmarks.bookmarks.truncate(limit);
+        }
+
+        Ok(bookmarks)
+    }
+
+    /// Run a write for each item within a single transaction per `mode`. Each
+    /// write is isolated in a savepoint so that a failure part way through a
+    /// write can't leave it partially applied, and so that every write in an
+    /// atomic batch can report its outcome before the batch is rolled back.
+    fn run_batch<I, T, F>(&self, items: &[I], mode: BatchMode, write: F) -> BatchResult<T>
+    where
+        F: Fn(&Connection, &I) -> Result<T, DbError>,
+    {
+        self.retry_locked(|| {
+            let tx = self.immediate_transaction()?;
+            let mut results = Vec::with_capacity(items.len());
+
+            for item in items {
+                tx.execute_batch("SAVEPOINT batch_item;")?;
+
+                match write(&tx, item) {
+                    // There's no use trying further writes whilst locked out
+                    Err(DbError::Locked) => return Err(DbError::Locked),
+                    Ok(res) => {
+                        tx.execute_batch("RELEASE batch_item;")?;
+                        results.push(Ok(res));
+                    }
+                    Err(err) => {
+                        tx.execute_batch("ROLLBACK TO batch_item; RELEASE batch_item;")?;
+                        results.push(Err(err));
+                    }
+                }
+            }
+
+            if mode == BatchMode::Atomic && results.iter().any(Result::is_err) {
+                // Dropping the transaction rolls it back
+                return Ok(results
+                    .into_iter()
+                    .map(|res| res.and(Err(DbError::RolledBack)))
+                    .collect());
+            }
+
+            tx.commit()?;
+
+            Ok(results)
+        })
+    }
+
+    /// Rewrite the tags of every bookmark tagged with any of `tags` within a
+    /// single transaction, returning the number of bookmarks changed.
+    fn rewrite_tags<F>(&self, tags: &Tags, rewrite: F) -> Result<usize, DbError>
+    where
+        F: Fn(Tags) -> Tags,
+    {
+        let mut each = tags.iter();
+        let candidates = match each.next() {
+            Some(first) => each.fold(TagQuery::Tag(first.clone()), |q, tag| {
+                TagQuery::Or(Box::new(q), Box::new(TagQuery::Tag(tag.clone())))
+            }),
+            None => return Ok(0),
+        };
+
+        self.retry_locked(|| {
+            let tx = self.immediate_transaction()?;
+            let mut params = Vec::new();
+            let query = format!(
+                "SELECT id, tags FROM bookmarks WHERE {};",
+                tag_prefilter_sql(&candidates, &mut params)
+            );
+            let rows = tx
+                .prepare(&query)?
+                .query_map(&params, |row| {
+                    Ok((
+                        row.get::<_, BookmarkId>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                    ))
+                })?
+                .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+            let mut updated = 0;
+            // Candidates are only loosely selected, so compare them as read
+            for (id, old_tags) in rows {
+                let old_tags = Tags::parse(&old_tags.unwrap_or_default());
+                if !candidates.matches(&old_tags) {
+                    continue;
+                }
+
+                let new_tags = rewrite(old_tags.clone());
+
+                if new_tags != old_tags {
+                    tx.execute(
+                        "UPDATE bookmarks SET tags = ?2 WHERE id = ?1;",
+                        &[&id as &dyn ToSql, &new_tags.to_string()],
+                    )?;
+                    updated += 1;
+                }
+            }
+
+            tx.commit()?;
+
+            Ok(updated)
+        })
+    }
+}
+
+/// Every column the host relies upon, and its type, per Buku's schema.
+const BUKU_COLUMNS: &[(&str, &str)] = &[
+    ("id", "integer"),
+    ("URL", "text"),
+    ("metadata", "text"),
+    ("tags", "text"),
+    ("desc", "text"),
+    ("flags", "integer"),
+];
+
+/// Check that a database has a bookmarks table compatible with Buku's schema.
+/// Additional columns are permitted.
+fn validate_schema(conn: &Connection) -> Result<(), DbError> {
+    let columns = conn
+        .prepare("PRAGMA table_info(bookmarks);")?
+        .query_map(NO_PARAMS, |row| {
+            Ok((row.get::<_, String>("name")?, row.get::<_, String>("type")?))
+        })?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+    if columns.is_empty() {
+        return Err(DbError::IncompatibleSchema(String::from(
+            "No bookmarks table.",
+        )));
+    }
+
+    for (name, expected_type) in BUKU_COLUMNS {
+        // SQLite identifiers are case-insensitive
+        match columns.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)) {
+            None => return Err(DbError::IncompatibleSchema(format!("No {} column.", name))),
+            Some((_, actual_type)) if !actual_type.eq_ignore_ascii_case(expected_type) => {
+                return Err(DbError::IncompatibleSchema(format!(
+                    "The {} column is of type {} rather than {}.",
+                    name, actual_type, expected_type
+                )))
+            }
+            Some(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// The ID of the bookmark with this exact URL, if any.
+fn find_url_id(conn: &Connection, url: &str) -> Result<Option<BookmarkId>, rusqlite::Error> {
+    conn.query_row("SELECT id FROM bookmarks WHERE URL = ?1;", &[url], |row| {
+        row.get(0)
+    })
+    .optional()
+}
+
+/// Move the bookmark with the highest ID into a freed ID, as Buku does after
+/// deleting a bookmark.
+fn compact_into(conn: &Connection, freed: BookmarkId) -> Result<Option<IdMove>, rusqlite::Error> {
+    let max: Option<BookmarkId> =
+        conn.query_row("SELECT MAX(id) FROM bookmarks;", NO_PARAMS, |row| {
+            row.get(0)
+        })?;
+
+    match max {
+        Some(max) if max > freed => {
+            conn.execute("UPDATE bookmarks SET id = ?2 WHERE id = ?1;", [max, freed])?;
+
+            Ok(Some(IdMove {
+                from: max,
+                to: freed,
+            }))
+        }
+        _ => Ok(None),
+    }
 }
 
+/// The columns selected for `map_db_bookmark`. These are selected by name so
+/// that any columns added by future versions of Buku are ignored.
+const BOOKMARK_COLUMNS: &str = "id, URL, metadata, tags, desc, flags";
+
 // Supply defaults for nullable fields (per SQLite schema)
-fn map_db_bookmark(row: &Row) -> Result<SavedBookmark, DbError> {
+fn map_db_bookmark(row: &Row) -> Result<SavedBookmark, rusqlite::Error> {
     Ok(SavedBookmark {
-        id: row.get(0)?,
-        url: row.get(1).unwrap_or_default(),
-        metadata: row.get(2).unwrap_or_default(),
-        tags: row.get(3).unwrap_or_default(),
-        desc: row.get(4).unwrap_or_default(),
-        flags: row.get(5).unwrap_or_default(),
+        id: row.get("id")?,
+        url: row.get::<_, Option<String>>("URL")?.unwrap_or_default(),
+        metadata: row
+            .get::<_, Option<String>>("metadata")?
+            .unwrap_or_default(),
+        tags: Tags::parse(&row.get::<_, Option<String>>("tags")?.unwrap_or_default()),
+        desc: row.get::<_, Option<String>>("desc")?.unwrap_or_default(),
+        flags: row.get::<_, Option<i32>>("flags")?.unwrap_or_default(),
     })
 }
 
-impl BukuDatabase for SqliteDatabase {
-    fn get_all_bookmarks(&self) -> Result<Vec<SavedBookmark>, DbError> {
-        let query = "SELECT * FROM bookmarks;";
-        let mut stmt = self.connection.prepare(query)?;
+/// Map a row to a bookmark or, should it hold unexpected values such as text
+/// that isn't valid UTF-8, to the reason it couldn't be.
+fn map_db_row(row: &Row) -> Result<Result<SavedBookmark, SkippedRow>, rusqlite::Error> {
+    Ok(map_db_bookmark(row).map_err(|err| SkippedRow {
+        id: row.get("id").ok(),
+        reason: err.to_string(),
+    }))
+}
 
-        let bookmarks = stmt
-            .query_map(NO_PARAMS, map_db_bookmark)?
-            .filter_map(|bm| bm.ok())
-            .collect();
+/// The expression by which SQLite can sort bookmarks, if it can.
+fn sort_sql(sort: SortKey) -> Option<&'static str> {
+    match sort {
+        SortKey::Id => Some("id"),
+        SortKey::Title => Some("metadata COLLATE NOCASE"),
+        SortKey::Url => Some("URL"),
+        // Hosts are parsed from URLs, and tags from Buku's delimited format,
+        // outside of SQLite, so that malformed tags are counted as they're
+        // read
+        SortKey::Domain | SortKey::TagCount => None,
+    }
+}
 
-        Ok(bookmarks)
+/// Escape a string for use within a `LIKE` pattern with `ESCAPE '\\'`.
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Compile a tag query to a SQL condition, pushing its parameters, that
+/// matches at least the bookmarks it does. Tags are normalised as they're read,
+/// so the final say is left to `TagQuery::matches`.
+fn tag_prefilter_sql(query: &TagQuery, params: &mut Vec<String>) -> String {
+    match query {
+        // `LIKE` only folds ASCII, and whitespace within tags is collapsed as
+        // they're read, so look for each word of the tag anywhere
+        TagQuery::Tag(tag) if tag.is_ascii() => {
+            let words = tag
+                .split_whitespace()
+                .map(|word| {
+                    params.push(format!("%{}%", escape_like(word)));
+                    format!("tags LIKE ?{} ESCAPE '\\'", params.len())
+                })
+                .collect::<Vec<String>>();
+
+            if words.is_empty() {
+                String::from("1")
+            } else {
+                format!("({})", words.join(" AND "))
+            }
+        }
+        // Otherwise `LIKE` can't be relied upon, and a bookmark without a tag
+        // may have anything in its tags string
+        TagQuery::Tag(_) | TagQuery::Not(_) => String::from("1"),
+        TagQuery::And(l, r) => format!(
+            "({} AND {})",
+            tag_prefilter_sql(l, params),
+            tag_prefilter_sql(r, params)
+        ),
+        TagQuery::Or(l, r) => format!(
+            "({} OR {})",
+            tag_prefilter_sql(l, params),
+            tag_prefilter_sql(r, params)
+        ),
     }
+}
 
-    fn get_bookmarks_by_id(&self, ids: Vec<BookmarkId>) -> Result<Vec<SavedBookmark>, DbError> {
+impl BukuDatabase for SqliteDatabase {
+    fn get_all_bookmarks(&self, options: &ListOptions) -> Result<Bookmarks, DbError> {
+        self.list_bookmarks("1", NO_PARAMS, None, options)
+    }
+
+    fn get_bookmarks_by_id(&self, ids: Vec<BookmarkId>) -> Result<Bookmarks, DbError> {
         let query = format!(
-            "SELECT * FROM bookmarks WHERE id IN ({});",
+            "SELECT {} FROM bookmarks WHERE id IN ({}) ORDER BY id;",
+            BOOKMARK_COLUMNS,
             ids.iter()
                 .map(|n| n.to_string())
                 .collect::<Vec<String>>()
@@ -62,60 +734,669 @@ impl BukuDatabase for SqliteDatabase {
         let mut stmt = self.connection.prepare(&query)?;
 
         let bookmarks = stmt
-            .query_map(NO_PARAMS, map_db_bookmark)?
-            .filter_map(|bm| bm.ok())
-            .collect();
+            .query_map(NO_PARAMS, map_db_row)?
+            .collect::<Result<Bookmarks, rusqlite::Error>>()?;
 
         Ok(bookmarks)
     }
 
-    fn add_bookmarks(&self, bms: &[UnsavedBookmark]) -> Result<Vec<usize>, DbError> {
-        bms
-            .iter()
-            .map(|bm| {
-                let query =
-                    "INSERT INTO bookmarks(metadata, desc, tags, url, flags) VALUES (?1, ?2, ?3, ?4, ?5);";
-                self.connection.execute(
-                    query,
-                    &[
-                        &bm.metadata,
-                        &bm.desc,
-                        &bm.tags,
-                        &bm.url,
-                        &bm.flags as &dyn ToSql,
-                    ],
+    fn search_bookmarks(
+        &self,
+        keywords: &[String],
+        mode: SearchMode,
+        options: &ListOptions,
+    ) -> Result<Bookmarks, DbError> {
+        if keywords.is_empty() {
+            return Ok(Bookmarks::default());
+        }
+
+        // Each keyword is bound once and referenced by every column check
+        let clauses = (1..=keywords.len())
+            .map(|n| {
+                format!(
+                    "(URL LIKE ?{n} ESCAPE '\\' OR metadata LIKE ?{n} ESCAPE '\\' OR tags LIKE ?{n} ESCAPE '\\' OR desc LIKE ?{n} ESCAPE '\\')",
+                    n = n
                 )
             })
-            .collect()
+            .collect::<Vec<String>>()
+            .join(match mode {
+                SearchMode::Any => " OR ",
+                SearchMode::All => " AND ",
+            });
+        let patterns: Vec<String> = keywords
+            .iter()
+            .map(|kw| format!("%{}%", escape_like(kw)))
+            .collect();
+
+        self.list_bookmarks(&clauses, &patterns, None, options)
+    }
+
+    fn get_bookmarks_by_tags(
+        &self,
+        query: &TagQuery,
+        options: &ListOptions,
+    ) -> Result<Bookmarks, DbError> {
+        let mut params = Vec::new();
+        let filter = tag_prefilter_sql(query, &mut params);
+
+        self.list_bookmarks(
+            &filter,
+            &params,
+            Some(&|bm| query.matches(&bm.tags)),
+            options,
+        )
+    }
+
+    fn get_bookmarks_by_host(
+        &self,
+        host: &str,
+        include_subdomains: bool,
+        options: &ListOptions,
+    ) -> Result<Bookmarks, DbError> {
+        // Narrow down the candidates in SQL before parsing their hosts
+        let pattern = format!("%{}%", escape_like(host.trim().trim_end_matches('.')));
+
+        self.list_bookmarks(
+            "URL LIKE ?1 ESCAPE '\\'",
+            &[pattern],
+            Some(&|bm| is_on_host(&bm.url, host, include_subdomains)),
+            options,
+        )
+    }
+
+    fn get_bookmarks_by_url_prefix(
+        &self,
+        prefix: &str,
+        options: &ListOptions,
+    ) -> Result<Bookmarks, DbError> {
+        // Unlike `LIKE`, `substr` is case-sensitive
+        self.list_bookmarks("substr(URL, 1, length(?1)) = ?1", &[prefix], None, options)
+    }
+
+    fn get_bookmarks_by_urls(
+        &self,
+        urls: &[String],
+        matching: &UrlMatch,
+    ) -> Result<UrlMatches, DbError> {
+        match matching {
+            UrlMatch::Exact => {
+                let mut stmt = self.connection.prepare(&format!(
+                    "SELECT {} FROM bookmarks WHERE URL = ?1;",
+                    BOOKMARK_COLUMNS
+                ))?;
+                let mut matches = UrlMatches::default();
+
+                for url in urls {
+                    let bms = stmt
+                        .query_map(&[url], map_db_row)?
+                        .collect::<Result<Bookmarks, rusqlite::Error>>()?;
+
+                    matches.matches.push(bms.bookmarks);
+                    matches.skipped.extend(bms.skipped);
+                }
+
+                Ok(matches)
+            }
+            // Normalisation can't be expressed in SQL, so normalise every
+            // bookmark's URL up front. Rows that can't be read can't be
+            // normalised, so might match any URL
+            UrlMatch::Normalised(ignored_params) => {
+                let all = self.get_all_bookmarks(&ListOptions::default())?;
+                let mut by_url: HashMap<String, Vec<SavedBookmark>> = HashMap::new();
+
+                for bm in all.bookmarks {
+                    by_url
+                        .entry(normalise_url(&bm.url, ignored_params))
+                        .or_default()
+                        .push(bm);
+                }
+
+                Ok(UrlMatches {
+                    matches: urls
+                        .iter()
+                        .map(|url| {
+                            by_url
+                                .get(&normalise_url(url, ignored_params))
+                                .cloned()
+                                .unwrap_or_default()
+                        })
+                        .collect(),
+                    skipped: all.skipped,
+                })
+            }
+        }
+    }
+
+    fn data_version(&self) -> Result<i64, DbError> {
+        let version = self
+            .connection
+            .query_row("PRAGMA data_version;", NO_PARAMS, |row| row.get(0))?;
+
+        Ok(version)
+    }
+
+    fn sync_token(&self) -> Result<String, DbError> {
+        if !sync::is_tracking(&self.connection)? {
+            self.retry_locked(|| {
+                let tx = self.immediate_transaction()?;
+
+                // Another connection may have beaten us to it
+                if !sync::is_tracking(&tx)? {
+                    sync::start_tracking(&tx)?;
+                }
+
+                tx.commit()?;
+
+                Ok(())
+            })?;
+        }
+
+        let token = sync::current_token(&self.connection)?.unwrap_or_default();
+
+        Ok(token.to_string())
     }
 
-    fn update_bookmarks(&self, bms: &[SavedBookmark]) -> Result<Vec<usize>, DbError> {
-        bms
+    fn changes_since(&self, token: &str) -> Result<SyncChanges, DbError> {
+        let token = token
+            .parse::<i64>()
+            .map_err(|_| DbError::SyncTokenExpired)?;
+
+        if !sync::is_tracking(&self.connection)? {
+            self.sync_token()?;
+
+            return Err(DbError::SyncTokenExpired);
+        }
+
+        if sync::needs_pruning(&self.connection)? {
+            self.retry_locked(|| {
+                let tx = self.immediate_transaction()?;
+                sync::prune(&tx)?;
+                tx.commit()?;
+
+                Ok(())
+            })?;
+        }
+
+        // Read the changes and the bookmarks they refer to as of one moment
+        let tx = Transaction::new_unchecked(&self.connection, TransactionBehavior::Deferred)?;
+        let ids = sync::changed_since(&tx, token)?.ok_or(DbError::SyncTokenExpired)?;
+        let latest = sync::current_token(&tx)?.unwrap_or_default();
+        let bookmarks = self.get_bookmarks_by_id(ids.clone())?;
+        tx.commit()?;
+
+        let present = bookmarks
+            .bookmarks
             .iter()
-            .map(|bm| {
-                let query = "UPDATE bookmarks SET (metadata, desc, tags, url, flags) = (?2, ?3, ?4, ?5, ?6) WHERE id = ?1;";
-                self.connection.execute(
-                    query,
-                    &[
-                        &bm.id,
-                        &bm.metadata as &dyn ToSql,
-                        &bm.desc,
-                        &bm.tags,
-                        &bm.url,
-                        &bm.flags,
-                    ],
-                )
+            .map(|bm| Some(bm.id))
+            .chain(bookmarks.skipped.iter().map(|row| row.id))
+            .collect::<HashSet<_>>();
+        let deleted = ids
+            .into_iter()
+            .filter(|id| !present.contains(&Some(*id)))
+            .collect();
+
+        Ok(SyncChanges {
+            bookmarks,
+            deleted,
+            token: latest.to_string(),
+        })
+    }
+
+    fn get_tags(&self) -> Result<TagCounts, DbError> {
+        let all = self.get_all_bookmarks(&ListOptions::default())?;
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+
+        for tag in all.bookmarks.into_iter().flat_map(|bm| bm.tags) {
+            *counts.entry(tag).or_insert(0) += 1;
+        }
+
+        Ok(TagCounts {
+            tags: counts
+                .into_iter()
+                .map(|(tag, count)| TagCount { tag, count })
+                .collect(),
+            skipped: all.skipped,
+        })
+    }
+
+    fn rename_tags(&self, from: &[String], to: &str) -> Result<usize, DbError> {
+        let from = from.iter().cloned().collect::<Tags>();
+
+        self.rewrite_tags(&from, |tags| {
+            tags.into_iter()
+                .filter(|tag| !from.contains(tag))
+                .chain(Tags::parse(to))
+                .collect()
+        })
+    }
+
+    fn delete_tags(&self, tags: &[String]) -> Result<usize, DbError> {
+        let tags = tags.iter().cloned().collect::<Tags>();
+
+        self.rewrite_tags(&tags, |bm_tags| {
+            bm_tags
+                .into_iter()
+                .filter(|tag| !tags.contains(tag))
+                .collect()
+        })
+    }
+
+    fn add_bookmarks(
+        &self,
+        bms: &[UnsavedBookmark],
+        policy: ConflictPolicy,
+        mode: BatchMode,
+    ) -> BatchResult<AddedBookmark> {
+        self.run_batch(bms, mode, |conn, bm| {
+            if let Some(id) = find_url_id(conn, &bm.url)? {
+                let existing = conn.query_row(
+                    &format!("SELECT {} FROM bookmarks WHERE id = ?1;", BOOKMARK_COLUMNS),
+                    [id],
+                    map_db_bookmark,
+                )?;
+                let bookmark = match policy {
+                    ConflictPolicy::Reject => return Err(DbError::DuplicateUrl(id)),
+                    ConflictPolicy::Skip => existing,
+                    ConflictPolicy::MergeTags => SavedBookmark {
+                        tags: existing.tags.iter().chain(bm.tags.iter()).cloned().collect(),
+                        ..existing
+                    },
+                    ConflictPolicy::Overwrite => SavedBookmark {
+                        id,
+                        url: bm.url.clone(),
+                        metadata: bm.metadata.clone(),
+                        tags: bm.tags.clone(),
+                        desc: bm.desc.clone(),
+                        flags: bm.flags,
+                    },
+                };
+
+                if policy != ConflictPolicy::Skip {
+                    let query = "UPDATE bookmarks SET (metadata, desc, tags, flags) = (?2, ?3, ?4, ?5) WHERE id = ?1;";
+                    conn.execute(
+                        query,
+                        &[
+                            &id,
+                            &bookmark.metadata as &dyn ToSql,
+                            &bookmark.desc,
+                            &bookmark.tags.to_string(),
+                            &bookmark.flags,
+                        ],
+                    )?;
+                }
+
+                return Ok(AddedBookmark {
+                    bookmark,
+                    existing: true,
+                });
+            }
+
+            let query =
+                "INSERT INTO bookmarks(metadata, desc, tags, url, flags) VALUES (?1, ?2, ?3, ?4, ?5);";
+            conn.execute(
+                query,
+                &[
+                    &bm.metadata,
+                    &bm.desc,
+                    &bm.tags.to_string(),
+                    &bm.url,
+                    &bm.flags as &dyn ToSql,
+                ],
+            )?;
+
+            let rowid = conn.last_insert_rowid();
+            let id = BookmarkId::try_from(rowid)
+                .map_err(|_| rusqlite::Error::IntegralValueOutOfRange(0, rowid))?;
+
+            Ok(AddedBookmark {
+                bookmark: SavedBookmark {
+                    id,
+                    url: bm.url.clone(),
+                    metadata: bm.metadata.clone(),
+                    tags: bm.tags.clone(),
+                    desc: bm.desc.clone(),
+                    flags: bm.flags,
+                },
+                existing: false,
             })
-            .collect()
+        })
     }
 
-    fn delete_bookmarks(&self, bm_ids: &[BookmarkId]) -> Result<Vec<usize>, DbError> {
-        bm_ids
-            .iter()
-            .map(|bm_id| {
+    fn update_bookmarks(&self, bms: &[SavedBookmark], mode: BatchMode) -> BatchResult<BookmarkId> {
+        self.run_batch(bms, mode, |conn, bm| {
+            match find_url_id(conn, &bm.url)? {
+                Some(id) if id != bm.id => return Err(DbError::DuplicateUrl(id)),
+                _ => {}
+            }
+
+            let query = "UPDATE bookmarks SET (metadata, desc, tags, url, flags) = (?2, ?3, ?4, ?5, ?6) WHERE id = ?1;";
+            let updated = conn.execute(
+                query,
+                &[
+                    &bm.id,
+                    &bm.metadata as &dyn ToSql,
+                    &bm.desc,
+                    &bm.tags.to_string(),
+                    &bm.url,
+                    &bm.flags,
+                ],
+            )?;
+
+            match updated {
+                0 => Err(DbError::NotFound(bm.id)),
+                _ => Ok(bm.id),
+            }
+        })
+    }
+
+    fn delete_bookmarks(
+        &self,
+        bm_ids: &[BookmarkId],
+        compact: bool,
+        mode: BatchMode,
+    ) -> BatchResult<DeletedBookmark> {
+        delete_highest_first(bm_ids, |ids| {
+            self.run_batch(ids, mode, |conn, bm_id| {
                 let query = "DELETE FROM bookmarks WHERE id = ?1;";
-                self.connection.execute(query, &[bm_id])
+
+                if conn.execute(query, &[bm_id])? == 0 {
+                    return Err(DbError::NotFound(*bm_id));
+                }
+
+                Ok(DeletedBookmark {
+                    id: *bm_id,
+                    moved: if compact {
+                        compact_into(conn, *bm_id)?
+                    } else {
+                        None
+                    },
+                })
             })
-            .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buku::conformance::unsaved;
+
+    fn deleted(
+        id: BookmarkId,
+        moved: Option<(BookmarkId, BookmarkId)>,
+    ) -> Result<DeletedBookmark, DbError> {
+        Ok(DeletedBookmark {
+            id,
+            moved: moved.map(|(from, to)| IdMove { from, to }),
+        })
+    }
+
+    #[test]
+    fn test_compose_id_moves() {
+        assert_eq!(compose_id_moves(&[]), Vec::new());
+
+        // Deleting 2 then 5 out of 1-6 moves 6 to 5, and then to 2
+        assert_eq!(
+            compose_id_moves(&[
+                deleted(2, Some((5, 2))),
+                Err(DbError::NotFound(9)),
+                deleted(5, Some((6, 5))),
+            ]),
+            vec![IdMove { from: 6, to: 2 }],
+        );
+
+        assert_eq!(
+            compose_id_moves(&[
+                deleted(1, Some((6, 1))),
+                deleted(3, Some((7, 3))),
+                deleted(8, None)
+            ]),
+            vec![IdMove { from: 7, to: 3 }, IdMove { from: 6, to: 1 }],
+        );
+    }
+
+    #[test]
+    fn test_validate_schema() {
+        let conn = Connection::open_in_memory().unwrap();
+        assert!(matches!(
+            validate_schema(&conn),
+            Err(DbError::IncompatibleSchema(_))
+        ));
+
+        conn.execute_batch("CREATE TABLE bookmarks (id integer PRIMARY KEY, URL text, metadata text, tags text, desc text);")
+            .unwrap();
+        assert!(matches!(
+            validate_schema(&conn),
+            Err(DbError::IncompatibleSchema(reason)) if reason == "No flags column."
+        ));
+
+        conn.execute_batch("ALTER TABLE bookmarks ADD COLUMN flags text;")
+            .unwrap();
+        assert!(matches!(
+            validate_schema(&conn),
+            Err(DbError::IncompatibleSchema(_))
+        ));
+
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE bookmarks (id INTEGER PRIMARY KEY, url TEXT, metadata TEXT, tags TEXT, desc TEXT, flags INTEGER, added TEXT);")
+            .unwrap();
+        assert!(validate_schema(&conn).is_ok());
+    }
+
+    #[test]
+    fn test_lock_retries() {
+        let path = std::env::temp_dir().join(format!("bukubrow-lock-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let lock_config = LockConfig {
+            busy_timeout: Duration::from_millis(10),
+            retries: 0,
+        };
+        let db = SqliteDatabase::create(&path, lock_config).unwrap();
+
+        // Hold the write lock as the Buku CLI might
+        let buku = Connection::open(&path).unwrap();
+        buku.execute_batch("BEGIN IMMEDIATE;").unwrap();
+
+        assert!(matches!(
+            db.delete_bookmarks(&[1], true, BatchMode::Atomic),
+            Err(DbError::Locked)
+        ));
+
+        // Release the lock whilst the host is retrying
+        let db = SqliteDatabase::new(
+            &path,
+            LockConfig {
+                retries: 5,
+                ..lock_config
+            },
+        )
+        .unwrap();
+        let release = std::thread::spawn(move || {
+            sleep(LOCK_RETRY_BACKOFF);
+            buku.execute_batch("COMMIT;").unwrap();
+        });
+
+        assert!(matches!(
+            db.delete_bookmarks(&[1], true, BatchMode::Atomic)
+                .unwrap()
+                .as_slice(),
+            [Err(DbError::NotFound(1))]
+        ));
+
+        release.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_malformed_tags() {
+        let db = SqliteDatabase::create(Path::new(":memory:"), LockConfig::default()).unwrap();
+        db.connection
+            .execute_batch(
+                "INSERT INTO bookmarks (id, URL, tags) VALUES (1, 'a', ',x,y,'), (2, 'b', 'x,y,z'), (3, 'c', NULL), (4, 'd', ',x,X,x,'), (5, 'e', ', Rust ,cli'), (6, 'f', ',rustacean,');",
+            )
+            .unwrap();
+        let ids = |bms: Bookmarks| bms.bookmarks.iter().map(|bm| bm.id).collect::<Vec<_>>();
+        let tagged = |query: &str| {
+            ids(db
+                .get_bookmarks_by_tags(&TagQuery::parse(query).unwrap(), &ListOptions::default())
+                .unwrap())
+        };
+
+        assert_eq!(
+            ids(db
+                .get_all_bookmarks(&ListOptions {
+                    sort: SortKey::TagCount,
+                    ..ListOptions::default()
+                })
+                .unwrap()),
+            vec![3, 4, 6, 1, 5, 2]
+        );
+        assert_eq!(tagged("rust"), vec![5]);
+        assert_eq!(tagged("- rust"), vec![1, 2, 3, 4, 6]);
+        assert_eq!(tagged("cli + rust | z"), vec![2, 5]);
+
+        assert_eq!(db.rename_tags(&[String::from("RUST")], "lang").unwrap(), 1);
+        assert_eq!(db.delete_tags(&[String::from("x")]).unwrap(), 3);
+        assert_eq!(
+            db.get_all_bookmarks(&ListOptions::default())
+                .unwrap()
+                .bookmarks
+                .iter()
+                .map(|bm| bm.tags.to_string())
+                .collect::<Vec<_>>(),
+            vec![",y,", ",y,z,", ",", ",", ",cli,lang,", ",rustacean,"]
+        );
+    }
+
+    #[test]
+    fn test_unreadable_rows() {
+        let db = SqliteDatabase::create(Path::new(":memory:"), LockConfig::default()).unwrap();
+        db.connection
+            .execute_batch(
+                "INSERT INTO bookmarks (id, URL, metadata, tags) VALUES (1, 'a', 'A', ',x,'), (2, 'b', CAST(X'FF' AS TEXT), ',x,');",
+            )
+            .unwrap();
+        let skipped =
+            |rows: Vec<SkippedRow>| rows.into_iter().map(|row| row.id).collect::<Vec<_>>();
+
+        let tags = db.get_tags().unwrap();
+
+        assert_eq!(
+            tags.tags,
+            vec![TagCount {
+                tag: String::from("x"),
+                count: 1
+            }]
+        );
+        assert_eq!(skipped(tags.skipped), vec![Some(2)]);
+
+        let urls = [String::from("a"), String::from("b")];
+
+        for matching in &[UrlMatch::Exact, UrlMatch::Normalised(Vec::new())] {
+            let matches = db.get_bookmarks_by_urls(&urls, matching).unwrap();
+
+            assert_eq!(
+                matches.matches.iter().map(Vec::len).collect::<Vec<_>>(),
+                vec![1, 0]
+            );
+            assert_eq!(skipped(matches.skipped), vec![Some(2)]);
+        }
+
+        // Limits count bookmarks listed, however the rows are sorted
+        db.connection
+            .execute_batch("INSERT INTO bookmarks (id, URL) VALUES (3, 'c');")
+            .unwrap();
+
+        for &sort in &[SortKey::Id, SortKey::Domain] {
+            let listed = db
+                .get_all_bookmarks(&ListOptions {
+                    sort,
+                    descending: true,
+                    limit: Some(2),
+                })
+                .unwrap();
+
+            assert_eq!(
+                listed.bookmarks.iter().map(|bm| bm.id).collect::<Vec<_>>(),
+                vec![3, 1]
+            );
+            assert_eq!(skipped(listed.skipped), vec![Some(2)]);
+        }
+    }
+
+    #[test]
+    fn test_sync_changes() {
+        let db = SqliteDatabase::create(Path::new(":memory:"), LockConfig::default()).unwrap();
+        let ids = |changes: &SyncChanges| {
+            changes
+                .bookmarks
+                .bookmarks
+                .iter()
+                .map(|bm| bm.id)
+                .collect::<Vec<_>>()
+        };
+
+        db.add_bookmarks(
+            &[
+                unsaved("a", "", ""),
+                unsaved("b", "", ""),
+                unsaved("c", "", ""),
+            ],
+            ConflictPolicy::Reject,
+            BatchMode::Atomic,
+        )
+        .unwrap();
+
+        let token = db.sync_token().unwrap();
+        let changes = db.changes_since(&token).unwrap();
+        assert!(ids(&changes).is_empty());
+        assert!(changes.deleted.is_empty());
+        assert_eq!(changes.token, token);
+
+        let mut b = db.get_bookmarks_by_id(vec![2]).unwrap().bookmarks.remove(0);
+        b.desc = String::from("updated");
+        db.update_bookmarks(&[b], BatchMode::Atomic).unwrap();
+        db.delete_bookmarks(&[1], false, BatchMode::Atomic).unwrap();
+        db.add_bookmarks(
+            &[unsaved("d", "", "")],
+            ConflictPolicy::Reject,
+            BatchMode::Atomic,
+        )
+        .unwrap();
+
+        let changes = db.changes_since(&token).unwrap();
+        let mut changed = ids(&changes);
+        changed.sort_unstable();
+        assert_eq!(changed, vec![2, 4]);
+        assert_eq!(changes.deleted, vec![1]);
+        assert!(db
+            .changes_since(&changes.token)
+            .unwrap()
+            .bookmarks
+            .bookmarks
+            .is_empty());
+
+        for unknown in &["", "sync", "0"] {
+            assert!(matches!(
+                db.changes_since(unknown),
+                Err(DbError::SyncTokenExpired)
+            ));
+        }
+
+        // Changes may go unrecorded without every trigger
+        db.connection
+            .execute_batch("DROP TRIGGER bukubrow_track_update;")
+            .unwrap();
+        assert!(matches!(
+            db.changes_since(&changes.token),
+            Err(DbError::SyncTokenExpired)
+        ));
+        let restarted = db.sync_token().unwrap();
+        assert!(restarted.parse::<i64>().unwrap() > changes.token.parse::<i64>().unwrap());
+        assert!(matches!(
+            db.changes_since(&changes.token),
+            Err(DbError::SyncTokenExpired)
+        ));
+        assert!(db.changes_since(&restarted).is_ok());
     }
 }
@@ -0,0 +1,514 @@
+//! A database held entirely in memory with the same semantics as Buku's, for
+//! trying out the host without touching any real bookmarks, and as a
+//! reference against which to check `SqliteDatabase`.
+
+use super::database::{
+    delete_highest_first, sort_bookmarks, AddedBookmark, BatchMode, BatchResult, BukuDatabase,
+    DbError, DeletedBookmark, SyncChanges,
+};
+use super::tags::TagQuery;
+use super::types::{
+    BookmarkId, Bookmarks, ConflictPolicy, IdMove, ListOptions, SavedBookmark, SearchMode,
+    TagCount, TagCounts, Tags, UnsavedBookmark, UrlMatch, UrlMatches,
+};
+use super::urls::{is_on_host, normalise_url};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Default)]
+struct State {
+    bookmarks: BTreeMap<BookmarkId, SavedBookmark>,
+    /// The ID of every bookmark written, in order, as logged by the triggers
+    /// of `SqliteDatabase`.
+    changes: Vec<BookmarkId>,
+}
+
+impl State {
+    fn find_url(&self, url: &str) -> Option<&SavedBookmark> {
+        self.bookmarks.values().find(|bm| bm.url == url)
+    }
+
+    /// As SQLite assigns row IDs to tables without `AUTOINCREMENT`.
+    fn next_id(&self) -> Result<BookmarkId, DbError> {
+        match self.bookmarks.keys().next_back() {
+            Some(max) => max.checked_add(1).ok_or(DbError::ConstraintViolation),
+            None => Ok(1),
+        }
+    }
+
+    fn put(&mut self, bm: SavedBookmark) {
+        self.changes.push(bm.id);
+        self.bookmarks.insert(bm.id, bm);
+    }
+
+    fn remove(&mut self, id: BookmarkId) -> Option<SavedBookmark> {
+        let bm = self.bookmarks.remove(&id)?;
+        self.changes.push(id);
+
+        Some(bm)
+    }
+
+    /// Move the bookmark with the highest ID into the freed ID, as per Buku.
+    fn compact_into(&mut self, freed: BookmarkId) -> Option<IdMove> {
+        let max = *self.bookmarks.keys().next_back()?;
+
+        if max <= freed {
+            return None;
+        }
+
+        let bm = self.remove(max)?;
+        self.put(SavedBookmark { id: freed, ..bm });
+
+        Some(IdMove {
+            from: max,
+            to: freed,
+        })
+    }
+
+    /// Rewrite the tags of every bookmark, returning the number changed.
+    fn rewrite_tags<F>(&mut self, rewrite: F) -> usize
+    where
+        F: Fn(Tags) -> Tags,
+    {
+        let rewritten = self
+            .bookmarks
+            .values()
+            .filter_map(|bm| {
+                let tags = rewrite(bm.tags.clone());
+
+                if tags == bm.tags {
+                    None
+                } else {
+                    Some(SavedBookmark { tags, ..bm.clone() })
+                }
+            })
+            .collect::<Vec<_>>();
+        let updated = rewritten.len();
+
+        for bm in rewritten {
+            self.put(bm);
+        }
+
+        updated
+    }
+}
+
+pub struct InMemoryDatabase {
+    state: RefCell<State>,
+    /// The token preceding the first change, which like those of
+    /// `SqliteDatabase` begins from the current time so that tokens issued by
+    /// another instance are never mistaken for this one's.
+    epoch: i64,
+}
+
+impl Default for InMemoryDatabase {
+    fn default() -> Self {
+        InMemoryDatabase {
+            state: RefCell::new(State::default()),
+            epoch: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as i64),
+        }
+    }
+}
+
+impl InMemoryDatabase {
+    /// Read the bookmarks satisfying `keep`, sorted and limited per `options`
+    /// as `SqliteDatabase` would.
+    fn list_bookmarks<F>(&self, keep: F, options: &ListOptions) -> Bookmarks
+    where
+        F: Fn(&SavedBookmark) -> bool,
+    {
+        let mut bookmarks = self
+            .state
+            .borrow()
+            .bookmarks
+            .values()
+            .filter(|bm| keep(bm))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // Leaving ties in order of ID in the same direction
+        if options.descending {
+            bookmarks.reverse();
+        }
+
+        sort_bookmarks(&mut bookmarks, options.sort, options.descending);
+
+        if let Some(limit) = options.limit {
+            bookmarks.truncate(limit);
+        }
+
+        Bookmarks {
+            bookmarks,
+            skipped: Vec::new(),
+        }
+    }
+
+    /// Run a write for each item per `mode`. Writes must check that they can
+    /// succeed before changing anything, so that a failure never leaves one
+    /// partially applied.
+    fn run_batch<I, T, F>(&self, items: &[I], mode: BatchMode, write: F) -> BatchResult<T>
+    where
+        F: Fn(&mut State, &I) -> Result<T, DbError>,
+    {
+        let mut state = self.state.borrow_mut();
+        let before = match mode {
+            BatchMode::Atomic => Some(state.clone()),
+            BatchMode::BestEffort => None,
+        };
+
+        let results = items
+            .iter()
+            .map(|item| write(&mut state, item))
+            .collect::<Vec<_>>();
+
+        match before {
+            Some(before) if results.iter().any(Result::is_err) => {
+                *state = before;
+
+                Ok(results
+                    .into_iter()
+                    .map(|res| res.and(Err(DbError::RolledBack)))
+                    .collect())
+            }
+            _ => Ok(results),
+        }
+    }
+}
+
+/// Whether `needle` appears within `haystack`, ignoring ASCII case as SQLite's
+/// `LIKE` does.
+fn like(haystack: &str, needle: &str) -> bool {
+    haystack
+        .to_ascii_lowercase()
+        .contains(&needle.to_ascii_lowercase())
+}
+
+impl BukuDatabase for InMemoryDatabase {
+    fn get_all_bookmarks(&self, options: &ListOptions) -> Result<Bookmarks, DbError> {
+        Ok(self.list_bookmarks(|_| true, options))
+    }
+
+    fn get_bookmarks_by_id(&self, ids: Vec<BookmarkId>) -> Result<Bookmarks, DbError> {
+        Ok(self.list_bookmarks(|bm| ids.contains(&bm.id), &ListOptions::default()))
+    }
+
+    fn search_bookmarks(
+        &self,
+        keywords: &[String],
+        mode: SearchMode,
+        options: &ListOptions,
+    ) -> Result<Bookmarks, DbError> {
+        let matches = |bm: &SavedBookmark, kw: &String| {
+            like(&bm.url, kw)
+                || like(&bm.metadata, kw)
+                || like(&bm.tags.to_string(), kw)
+                || like(&bm.desc, kw)
+        };
+
+        Ok(self.list_bookmarks(
+            |bm| match mode {
+                SearchMode::Any => keywords.iter().any(|kw| matches(bm, kw)),
+                SearchMode::All => {
+                    !keywords.is_empty() && keywords.iter().all(|kw| matches(bm, kw))
+                }
+            },
+            options,
+        ))
+    }
+
+    fn get_bookmarks_by_tags(
+        &self,
+        query: &TagQuery,
+        options: &ListOptions,
+    ) -> Result<Bookmarks, DbError> {
+        Ok(self.list_bookmarks(|bm| query.matches(&bm.tags), options))
+    }
+
+    fn get_bookmarks_by_host(
+        &self,
+        host: &str,
+        include_subdomains: bool,
+        options: &ListOptions,
+    ) -> Result<Bookmarks, DbError> {
+        Ok(self.list_bookmarks(|bm| is_on_host(&bm.url, host, include_subdomains), options))
+    }
+
+    fn get_bookmarks_by_url_prefix(
+        &self,
+        prefix: &str,
+        options: &ListOptions,
+    ) -> Result<Bookmarks, DbError> {
+        Ok(self.list_bookmarks(|bm| bm.url.starts_with(prefix), options))
+    }
+
+    fn get_bookmarks_by_urls(
+        &self,
+        urls: &[String],
+        matching: &UrlMatch,
+    ) -> Result<UrlMatches, DbError> {
+        let all = self.list_bookmarks(|_| true, &ListOptions::default());
+
+        Ok(UrlMatches {
+            matches: urls
+                .iter()
+                .map(|url| {
+                    all.bookmarks
+                        .iter()
+                        .filter(|bm| match matching {
+                            UrlMatch::Exact => &bm.url == url,
+                            UrlMatch::Normalised(ignored_params) => {
+                                normalise_url(&bm.url, ignored_params)
+                                    == normalise_url(url, ignored_params)
+                            }
+                        })
+                        .cloned()
+                        .collect()
+                })
+                .collect(),
+            skipped: Vec::new(),
+        })
+    }
+
+    /// Nothing but the host can change the database, so its version never
+    /// changes.
+    fn data_version(&self) -> Result<i64, DbError> {
+        Ok(0)
+    }
+
+    fn sync_token(&self) -> Result<String, DbError> {
+        let token = self.epoch + self.state.borrow().changes.len() as i64;
+
+        Ok(token.to_string())
+    }
+
+    fn changes_since(&self, token: &str) -> Result<SyncChanges, DbError> {
+        let state = self.state.borrow();
+        let seen = token
+            .parse::<i64>()
+            .ok()
+            .and_then(|token| usize::try_from(token - self.epoch).ok())
+            .filter(|seen| *seen <= state.changes.len())
+            .ok_or(DbError::SyncTokenExpired)?;
+
+        let mut ids = state.changes[seen..].to_vec();
+        ids.sort_unstable();
+        ids.dedup();
+
+        let (present, deleted): (Vec<_>, Vec<_>) = ids
+            .into_iter()
+            .partition(|id| state.bookmarks.contains_key(id));
+
+        Ok(SyncChanges {
+            bookmarks: Bookmarks {
+                bookmarks: present
+                    .into_iter()
+                    .filter_map(|id| state.bookmarks.get(&id).cloned())
+                    .collect(),
+                skipped: Vec::new(),
+            },
+            deleted,
+            token: (self.epoch + state.changes.len() as i64).to_string(),
+        })
+    }
+
+    fn get_tags(&self) -> Result<TagCounts, DbError> {
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+
+        for bm in self.state.borrow().bookmarks.values() {
+            for tag in bm.tags.iter() {
+                *counts.entry(tag.clone()).or_insert(0) += 1;
+            }
+        }
+
+        Ok(TagCounts {
+            tags: counts
+                .into_iter()
+                .map(|(tag, count)| TagCount { tag, count })
+                .collect(),
+            skipped: Vec::new(),
+        })
+    }
+
+    fn rename_tags(&self, from: &[String], to: &str) -> Result<usize, DbError> {
+        let from = from.iter().cloned().collect::<Tags>();
+
+        Ok(self.state.borrow_mut().rewrite_tags(|tags| {
+            if !tags.iter().any(|tag| from.contains(tag)) {
+                return tags;
+            }
+
+            tags.into_iter()
+                .filter(|tag| !from.contains(tag))
+                .chain(Tags::parse(to))
+                .collect()
+        }))
+    }
+
+    fn delete_tags(&self, tags: &[String]) -> Result<usize, DbError> {
+        let tags = tags.iter().cloned().collect::<Tags>();
+
+        Ok(self.state.borrow_mut().rewrite_tags(|bm_tags| {
+            bm_tags
+                .into_iter()
+                .filter(|tag| !tags.contains(tag))
+                .collect()
+        }))
+    }
+
+    fn add_bookmarks(
+        &self,
+        bms: &[UnsavedBookmark],
+        policy: ConflictPolicy,
+        mode: BatchMode,
+    ) -> BatchResult<AddedBookmark> {
+        self.run_batch(bms, mode, |state, bm| {
+            if let Some(existing) = state.find_url(&bm.url).cloned() {
+                let bookmark = match policy {
+                    ConflictPolicy::Reject => return Err(DbError::DuplicateUrl(existing.id)),
+                    ConflictPolicy::Skip => existing,
+                    ConflictPolicy::MergeTags => SavedBookmark {
+                        tags: existing
+                            .tags
+                            .iter()
+                            .chain(bm.tags.iter())
+                            .cloned()
+                            .collect(),
+                        ..existing
+                    },
+                    ConflictPolicy::Overwrite => SavedBookmark {
+                        id: existing.id,
+                        url: bm.url.clone(),
+                        metadata: bm.metadata.clone(),
+                        tags: bm.tags.clone(),
+                        desc: bm.desc.clone(),
+                        flags: bm.flags,
+                    },
+                };
+
+                if policy != ConflictPolicy::Skip {
+                    state.put(bookmark.clone());
+                }
+
+                return Ok(AddedBookmark {
+                    bookmark,
+                    existing: true,
+                });
+            }
+
+            let bookmark = SavedBookmark {
+                id: state.next_id()?,
+                url: bm.url.clone(),
+                metadata: bm.metadata.clone(),
+                tags: bm.tags.clone(),
+                desc: bm.desc.clone(),
+                flags: bm.flags,
+            };
+            state.put(bookmark.clone());
+
+            Ok(AddedBookmark {
+                bookmark,
+                existing: false,
+            })
+        })
+    }
+
+    fn update_bookmarks(&self, bms: &[SavedBookmark], mode: BatchMode) -> BatchResult<BookmarkId> {
+        self.run_batch(bms, mode, |state, bm| {
+            match state.find_url(&bm.url) {
+                Some(other) if other.id != bm.id => return Err(DbError::DuplicateUrl(other.id)),
+                _ => {}
+            }
+
+            if !state.bookmarks.contains_key(&bm.id) {
+                return Err(DbError::NotFound(bm.id));
+            }
+
+            state.put(bm.clone());
+
+            Ok(bm.id)
+        })
+    }
+
+    fn delete_bookmarks(
+        &self,
+        bm_ids: &[BookmarkId],
+        compact: bool,
+        mode: BatchMode,
+    ) -> BatchResult<DeletedBookmark> {
+        delete_highest_first(bm_ids, |ids| {
+            self.run_batch(ids, mode, |state, bm_id| {
+                state.remove(*bm_id).ok_or(DbError::NotFound(*bm_id))?;
+
+                Ok(DeletedBookmark {
+                    id: *bm_id,
+                    moved: if compact {
+                        state.compact_into(*bm_id)
+                    } else {
+                        None
+                    },
+                })
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buku::conformance::unsaved;
+
+    fn ids(bms: Bookmarks) -> Vec<BookmarkId> {
+        bms.bookmarks.into_iter().map(|bm| bm.id).collect()
+    }
+
+    #[test]
+    fn test_ids_and_compaction() {
+        let db = InMemoryDatabase::default();
+        let bms = ["a", "b", "c", "d"].map(|url| unsaved(url, "", ""));
+        db.add_bookmarks(&bms, ConflictPolicy::Reject, BatchMode::Atomic)
+            .unwrap();
+
+        assert_eq!(
+            ids(db.get_all_bookmarks(&ListOptions::default()).unwrap()),
+            vec![1, 2, 3, 4]
+        );
+
+        let deleted = db
+            .delete_bookmarks(&[1, 2], true, BatchMode::Atomic)
+            .unwrap();
+
+        assert_eq!(
+            deleted
+                .into_iter()
+                .map(|res| res.unwrap().moved)
+                .collect::<Vec<_>>(),
+            vec![
+                Some(IdMove { from: 3, to: 1 }),
+                Some(IdMove { from: 4, to: 2 })
+            ],
+        );
+        assert_eq!(
+            db.get_all_bookmarks(&ListOptions::default())
+                .unwrap()
+                .bookmarks
+                .into_iter()
+                .map(|bm| (bm.id, bm.url))
+                .collect::<Vec<_>>(),
+            vec![(1, String::from("c")), (2, String::from("d"))],
+        );
+
+        let added = db
+            .add_bookmarks(
+                &[unsaved("e", "", "")],
+                ConflictPolicy::Reject,
+                BatchMode::Atomic,
+            )
+            .unwrap();
+
+        assert_eq!(added[0].as_ref().unwrap().bookmark.id, 3);
+    }
+}
@@ -1,3 +1,10 @@
+pub mod changes;
+#[cfg(test)]
+pub mod conformance;
 pub mod database;
+pub mod memory;
+pub mod sync;
+pub mod tags;
 pub mod types;
+pub mod urls;
 pub mod utils;
@@ -0,0 +1,155 @@
+//! Tracking of changes to bookmarks, whether made by the host or by anything
+//! else such as the Buku CLI, so that a client can be sent only what's changed
+//! since it last synced.
+//!
+//! Triggers in the database log the ID of every bookmark inserted, updated or
+//! deleted to a side table, each with an ever-increasing sequence number. A
+//! sync token is the sequence number of the latest change a client has seen.
+
+use super::types::BookmarkId;
+use rusqlite::{Connection, OptionalExtension, NO_PARAMS};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The number of changes beyond which the oldest are forgotten, expiring any
+/// tokens that predate those remaining.
+const MAX_TRACKED_CHANGES: i64 = 10_000;
+
+/// Triggers that bookmark writes depend upon, so these are always dropped
+/// before the side table is.
+const DROP_TRACKING: &str = "
+    DROP TRIGGER IF EXISTS bukubrow_track_insert;
+    DROP TRIGGER IF EXISTS bukubrow_track_update;
+    DROP TRIGGER IF EXISTS bukubrow_track_delete;
+    DROP TABLE IF EXISTS bukubrow_changes;
+";
+
+/// A row without a bookmark ID marks the oldest token still valid.
+const CREATE_TRACKING: &str = "
+    CREATE TABLE bukubrow_changes (seq INTEGER PRIMARY KEY AUTOINCREMENT, bookmark_id INTEGER);
+    CREATE TRIGGER bukubrow_track_insert AFTER INSERT ON bookmarks BEGIN
+        INSERT INTO bukubrow_changes (bookmark_id) VALUES (new.id);
+    END;
+    CREATE TRIGGER bukubrow_track_update AFTER UPDATE ON bookmarks BEGIN
+        INSERT INTO bukubrow_changes (bookmark_id) VALUES (old.id), (new.id);
+    END;
+    CREATE TRIGGER bukubrow_track_delete AFTER DELETE ON bookmarks BEGIN
+        INSERT INTO bukubrow_changes (bookmark_id) VALUES (old.id);
+    END;
+";
+
+/// Whether the side table and all of its triggers are in place. Should any
+/// be missing, changes may have gone unrecorded.
+pub fn is_tracking(conn: &Connection) -> Result<bool, rusqlite::Error> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE name IN ('bukubrow_changes', 'bukubrow_track_insert', 'bukubrow_track_update', 'bukubrow_track_delete');",
+        NO_PARAMS,
+        |row| row.get(0),
+    )?;
+
+    Ok(count == 4)
+}
+
+/// (Re)create the side table and its triggers, expiring any existing tokens.
+/// Sequence numbers begin from the current time so that tokens issued before
+/// tracking was restarted remain expired.
+pub fn start_tracking(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64);
+    let start = now.max(current_token(conn)?.unwrap_or(0));
+
+    conn.execute_batch(DROP_TRACKING)?;
+    conn.execute_batch(CREATE_TRACKING)?;
+    conn.execute(
+        "INSERT INTO sqlite_sequence (name, seq) VALUES ('bukubrow_changes', ?1);",
+        [start],
+    )?;
+    conn.execute(
+        "INSERT INTO bukubrow_changes (bookmark_id) VALUES (NULL);",
+        NO_PARAMS,
+    )?;
+
+    Ok(())
+}
+
+/// The token for the latest change, if tracking has ever been started.
+pub fn current_token(conn: &Connection) -> Result<Option<i64>, rusqlite::Error> {
+    let exists: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE name = 'sqlite_sequence';",
+        NO_PARAMS,
+        |row| row.get(0),
+    )?;
+
+    if exists == 0 {
+        return Ok(None);
+    }
+
+    conn.query_row(
+        "SELECT seq FROM sqlite_sequence WHERE name = 'bukubrow_changes';",
+        NO_PARAMS,
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// The IDs of bookmarks changed since `token`, or nothing if it has expired
+/// or was never issued.
+pub fn changed_since(
+    conn: &Connection,
+    token: i64,
+) -> Result<Option<Vec<BookmarkId>>, rusqlite::Error> {
+    let oldest: Option<i64> = conn
+        .query_row(
+            "SELECT seq FROM bukubrow_changes WHERE bookmark_id IS NULL;",
+            NO_PARAMS,
+            |row| row.get(0),
+        )
+        .optional()?;
+    let latest = current_token(conn)?;
+
+    match (oldest, latest) {
+        (Some(oldest), Some(latest)) if oldest <= token && token <= latest => {}
+        _ => return Ok(None),
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT bookmark_id FROM bukubrow_changes WHERE seq > ?1 AND bookmark_id IS NOT NULL ORDER BY bookmark_id;",
+    )?;
+    let ids = stmt
+        .query_map([token], |row| row.get(0))?
+        .collect::<Result<Vec<BookmarkId>, _>>()?;
+
+    Ok(Some(ids))
+}
+
+/// Whether enough changes have been logged that the oldest should be pruned.
+pub fn needs_pruning(conn: &Connection) -> Result<bool, rusqlite::Error> {
+    let count: i64 =
+        conn.query_row("SELECT COUNT(*) FROM bukubrow_changes;", NO_PARAMS, |row| {
+            row.get(0)
+        })?;
+
+    Ok(count > MAX_TRACKED_CHANGES)
+}
+
+/// Forget the oldest half of the logged changes, moving the marker of the
+/// oldest valid token up to the last change forgotten.
+pub fn prune(conn: &Connection) -> Result<(), rusqlite::Error> {
+    let cutoff: Option<i64> = conn
+        .query_row(
+            "SELECT seq FROM bukubrow_changes ORDER BY seq DESC LIMIT 1 OFFSET ?1;",
+            [MAX_TRACKED_CHANGES / 2],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    if let Some(cutoff) = cutoff {
+        conn.execute("DELETE FROM bukubrow_changes WHERE seq <= ?1;", [cutoff])?;
+        conn.execute(
+            "INSERT INTO bukubrow_changes (seq, bookmark_id) VALUES (?1, NULL);",
+            [cutoff],
+        )?;
+    }
+
+    Ok(())
+}
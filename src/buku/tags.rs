@@ -0,0 +1,205 @@
+use super::types::Tags;
+
+/// A boolean expression over tags, such as `rust + cli - archived` or
+/// `work | personal`.
+///
+/// Operators must be separated from tags by whitespace so that tags such as
+/// `c++` and `e-mail` are unambiguous. In order of precedence:
+///
+/// - `( … )` groups an expression.
+/// - `-` negates the following term, or excludes it when used between terms.
+/// - `+` requires both terms.
+/// - `|` or `,` requires either term.
+#[derive(Debug, PartialEq)]
+pub enum TagQuery {
+    Tag(String),
+    Not(Box<TagQuery>),
+    And(Box<TagQuery>, Box<TagQuery>),
+    Or(Box<TagQuery>, Box<TagQuery>),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TagQueryError {
+    Empty,
+    UnexpectedToken(String),
+    UnexpectedEnd,
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Tag(String),
+    And,
+    Or,
+    Minus,
+    Open,
+    Close,
+}
+
+fn tokenise(input: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut words: Vec<&str> = Vec::new();
+
+    let flush = |words: &mut Vec<&str>, tokens: &mut Vec<Token>| {
+        if !words.is_empty() {
+            tokens.push(Token::Tag(words.join(" ").to_lowercase()));
+            words.clear();
+        }
+    };
+
+    let spaced = input
+        .replace(',', " , ")
+        .replace('(', " ( ")
+        .replace(')', " ) ");
+
+    for word in spaced.split_whitespace() {
+        let op = match word {
+            "+" => Some(Token::And),
+            "|" | "," => Some(Token::Or),
+            "-" => Some(Token::Minus),
+            "(" => Some(Token::Open),
+            ")" => Some(Token::Close),
+            _ => None,
+        };
+
+        match op {
+            Some(op) => {
+                flush(&mut words, &mut tokens);
+                tokens.push(op);
+            }
+            None => words.push(word),
+        }
+    }
+
+    flush(&mut words, &mut tokens);
+
+    tokens
+}
+
+struct Parser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+}
+
+impl Parser {
+    fn or(&mut self) -> Result<TagQuery, TagQueryError> {
+        let mut lhs = self.and()?;
+
+        while self.tokens.peek() == Some(&Token::Or) {
+            self.tokens.next();
+            lhs = TagQuery::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<TagQuery, TagQueryError> {
+        let mut lhs = self.unary()?;
+
+        loop {
+            match self.tokens.peek() {
+                Some(Token::And) => {
+                    self.tokens.next();
+                    lhs = TagQuery::And(Box::new(lhs), Box::new(self.unary()?));
+                }
+                Some(Token::Minus) => {
+                    self.tokens.next();
+                    let rhs = TagQuery::Not(Box::new(self.unary()?));
+                    lhs = TagQuery::And(Box::new(lhs), Box::new(rhs));
+                }
+                _ => break Ok(lhs),
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<TagQuery, TagQueryError> {
+        match self.tokens.next() {
+            Some(Token::Tag(tag)) => Ok(TagQuery::Tag(tag)),
+            Some(Token::Minus) => Ok(TagQuery::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let inner = self.or()?;
+
+                match self.tokens.next() {
+                    Some(Token::Close) => Ok(inner),
+                    Some(token) => Err(TagQueryError::UnexpectedToken(format!("{:?}", token))),
+                    None => Err(TagQueryError::UnexpectedEnd),
+                }
+            }
+            Some(token) => Err(TagQueryError::UnexpectedToken(format!("{:?}", token))),
+            None => Err(TagQueryError::UnexpectedEnd),
+        }
+    }
+}
+
+impl TagQuery {
+    pub fn parse(input: &str) -> Result<Self, TagQueryError> {
+        let tokens = tokenise(input);
+        if tokens.is_empty() {
+            return Err(TagQueryError::Empty);
+        }
+
+        let mut parser = Parser {
+            tokens: tokens.into_iter().peekable(),
+        };
+        let query = parser.or()?;
+
+        match parser.tokens.next() {
+            Some(token) => Err(TagQueryError::UnexpectedToken(format!("{:?}", token))),
+            None => Ok(query),
+        }
+    }
+
+    /// Whether a bookmark with these tags satisfies the query.
+    pub fn matches(&self, tags: &Tags) -> bool {
+        match self {
+            TagQuery::Tag(tag) => tags.contains(tag),
+            TagQuery::Not(q) => !q.matches(tags),
+            TagQuery::And(l, r) => l.matches(tags) && r.matches(tags),
+            TagQuery::Or(l, r) => l.matches(tags) || r.matches(tags),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(t: &str) -> Box<TagQuery> {
+        Box::new(TagQuery::Tag(String::from(t)))
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            TagQuery::parse("rust + cli - archived"),
+            Ok(TagQuery::And(
+                Box::new(TagQuery::And(tag("rust"), tag("cli"))),
+                Box::new(TagQuery::Not(tag("archived"))),
+            )),
+        );
+
+        assert_eq!(
+            TagQuery::parse("work | personal, Home Page"),
+            Ok(TagQuery::Or(
+                Box::new(TagQuery::Or(tag("work"), tag("personal"))),
+                tag("home page"),
+            )),
+        );
+
+        assert_eq!(
+            TagQuery::parse("c++ + (e-mail | -x)"),
+            Ok(TagQuery::And(
+                tag("c++"),
+                Box::new(TagQuery::Or(tag("e-mail"), tag("-x"))),
+            )),
+        );
+
+        assert_eq!(
+            TagQuery::parse("- archived"),
+            Ok(TagQuery::Not(tag("archived"))),
+        );
+
+        assert_eq!(TagQuery::parse("  "), Err(TagQueryError::Empty));
+        assert_eq!(TagQuery::parse("rust +"), Err(TagQueryError::UnexpectedEnd));
+        assert_eq!(TagQuery::parse("(rust"), Err(TagQueryError::UnexpectedEnd));
+        assert!(TagQuery::parse("rust )").is_err());
+    }
+}
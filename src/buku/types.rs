@@ -1,11 +1,121 @@
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserializer, Serializer};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::iter::FromIterator;
+
 pub type BookmarkId = u32;
 
+/// A normalised set of tags: trimmed, lower-case, sorted and without
+/// duplicates. Buku stores tags as a single delimited string such as
+/// `,rust,cli,`, which is what `Display` produces and what tags serialise to
+/// by default. Tags deserialise from either that format or an array.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tags(BTreeSet<String>);
+
+/// Tags are case-insensitive and may contain spaces, but not commas.
+fn normalise_tag(tag: &str) -> String {
+    tag.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+impl Tags {
+    /// Parse a comma-delimited string of tags, with or without Buku's
+    /// surrounding delimiters.
+    pub fn parse(tags: &str) -> Self {
+        std::iter::once(String::from(tags)).collect()
+    }
+
+    pub fn contains(&self, tag: &str) -> bool {
+        self.0.contains(&normalise_tag(tag))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.0.iter()
+    }
+}
+
+impl FromIterator<String> for Tags {
+    fn from_iter<I: IntoIterator<Item = String>>(iter: I) -> Self {
+        Tags(
+            iter.into_iter()
+                .flat_map(|tags| tags.split(',').map(normalise_tag).collect::<Vec<String>>())
+                .filter(|tag| !tag.is_empty())
+                .collect(),
+        )
+    }
+}
+
+impl IntoIterator for Tags {
+    type Item = String;
+    type IntoIter = std::collections::btree_set::IntoIter<String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+/// Buku's internal format. No tags are represented by a lone delimiter.
+impl fmt::Display for Tags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, ",")?;
+
+        for tag in &self.0 {
+            write!(f, "{},", tag)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl serde::Serialize for Tags {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+struct TagsVisitor;
+
+impl<'de> Visitor<'de> for TagsVisitor {
+    type Value = Tags;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a comma-delimited string or an array of tags")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Tags, E> {
+        Ok(Tags::parse(v))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Tags, A::Error> {
+        let mut tags = Vec::new();
+
+        while let Some(tag) = seq.next_element::<String>()? {
+            tags.push(tag);
+        }
+
+        Ok(tags.into_iter().collect())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Tags {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(TagsVisitor)
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct SavedBookmark {
     pub id: BookmarkId,
     pub url: String,
     pub metadata: String,
-    pub tags: String,
+    pub tags: Tags,
     pub desc: String,
     pub flags: i32,
 }
@@ -14,7 +124,192 @@ pub struct SavedBookmark {
 pub struct UnsavedBookmark {
     pub url: String,
     pub metadata: String,
-    pub tags: String,
+    pub tags: Tags,
     pub desc: String,
     pub flags: i32,
 }
+
+/// A row that couldn't be read as a bookmark, and why.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct SkippedRow {
+    pub id: Option<BookmarkId>,
+    pub reason: String,
+}
+
+/// Bookmarks read from the database, along with any rows that matched but
+/// couldn't be read.
+#[derive(Default)]
+pub struct Bookmarks {
+    pub bookmarks: Vec<SavedBookmark>,
+    pub skipped: Vec<SkippedRow>,
+}
+
+impl FromIterator<Result<SavedBookmark, SkippedRow>> for Bookmarks {
+    fn from_iter<I: IntoIterator<Item = Result<SavedBookmark, SkippedRow>>>(iter: I) -> Self {
+        let mut bms = Bookmarks::default();
+
+        for row in iter {
+            match row {
+                Ok(bm) => bms.bookmarks.push(bm),
+                Err(skipped) => bms.skipped.push(skipped),
+            }
+        }
+
+        bms
+    }
+}
+
+/// A distinct tag and the number of bookmarks it's applied to.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: usize,
+}
+
+/// Every distinct tag, along with any rows whose tags went uncounted as they
+/// couldn't be read.
+#[derive(Default)]
+pub struct TagCounts {
+    pub tags: Vec<TagCount>,
+    pub skipped: Vec<SkippedRow>,
+}
+
+/// The bookmarks matching each of several URLs, along with any rows that
+/// couldn't be read and so might also have matched.
+#[derive(Default)]
+pub struct UrlMatches {
+    pub matches: Vec<Vec<SavedBookmark>>,
+    pub skipped: Vec<SkippedRow>,
+}
+
+/// How multiple search keywords are combined, mirroring Buku's `-s` (any) and
+/// `-S` (all) flags.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    #[default]
+    Any,
+    All,
+}
+
+/// A field of a saved bookmark, named as it's serialised.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum BookmarkField {
+    Id,
+    Url,
+    Metadata,
+    Tags,
+    Desc,
+    Flags,
+}
+
+impl BookmarkField {
+    pub fn name(self) -> &'static str {
+        match self {
+            BookmarkField::Id => "id",
+            BookmarkField::Url => "url",
+            BookmarkField::Metadata => "metadata",
+            BookmarkField::Tags => "tags",
+            BookmarkField::Desc => "desc",
+            BookmarkField::Flags => "flags",
+        }
+    }
+}
+
+/// What to sort listed bookmarks by. Ties are broken by ID.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum SortKey {
+    /// The order in which the bookmarks were added.
+    #[default]
+    Id,
+    /// Case-insensitively.
+    Title,
+    Url,
+    /// The host of the URL.
+    Domain,
+    /// The number of tags.
+    TagCount,
+}
+
+/// How to sort and limit a listing of bookmarks.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Hash)]
+#[serde(default)]
+pub struct ListOptions {
+    pub sort: SortKey,
+    pub descending: bool,
+    /// The maximum number of bookmarks to list.
+    pub limit: Option<usize>,
+}
+
+/// A bookmark's change of ID.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct IdMove {
+    pub from: BookmarkId,
+    pub to: BookmarkId,
+}
+
+/// How bookmarks are matched against a URL.
+#[derive(Clone, Debug, PartialEq)]
+pub enum UrlMatch {
+    Exact,
+    /// Compare URLs per `normalise_url`, ignoring these query parameters.
+    Normalised(Vec<String>),
+}
+
+/// How to save a new bookmark whose URL is already bookmarked, given that
+/// Buku requires URLs to be unique.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ConflictPolicy {
+    /// Fail to save the new bookmark.
+    #[default]
+    Reject,
+    /// Leave the existing bookmark untouched.
+    Skip,
+    /// Add the new bookmark's tags to the existing bookmark.
+    MergeTags,
+    /// Replace the existing bookmark's fields with the new bookmark's.
+    Overwrite,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tags_parse() {
+        assert_eq!(
+            Tags::parse(",rust, CLI,,rust,  Home   Page ,")
+                .iter()
+                .collect::<Vec<&String>>(),
+            vec!["cli", "home page", "rust"],
+        );
+        assert!(Tags::parse(",").is_empty());
+        assert!(Tags::parse("").is_empty());
+        assert!(Tags::parse("rust,cli").contains("Rust"));
+    }
+
+    #[test]
+    fn test_tags_display() {
+        assert_eq!(Tags::parse("web,cli,web").to_string(), ",cli,web,");
+        assert_eq!(Tags::default().to_string(), ",");
+    }
+
+    #[test]
+    fn test_tags_serde() {
+        let tags = Tags::parse("rust,cli");
+
+        assert_eq!(serde_json::to_value(&tags).unwrap(), json!(",cli,rust,"));
+        assert_eq!(
+            serde_json::from_value::<Tags>(json!(",rust,cli,")).unwrap(),
+            tags
+        );
+        assert_eq!(
+            serde_json::from_value::<Tags>(json!(["Rust", "cli"])).unwrap(),
+            tags
+        );
+        assert!(serde_json::from_value::<Tags>(json!(1)).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+/// Query parameters that only track where a visitor came from, and are
+/// ignored by default when normalising URLs.
+pub const TRACKING_PARAMS: &[&str] = &[
+    "utm_source",
+    "utm_medium",
+    "utm_campaign",
+    "utm_term",
+    "utm_content",
+    "fbclid",
+    "gclid",
+    "mc_cid",
+    "mc_eid",
+];
+
+/// Reduce a URL to a form in which trivially different URLs for the same page
+/// are equal. The scheme, fragment, trailing slashes and any `ignored_params`
+/// query parameters are removed, and the host is lower-cased.
+pub fn normalise_url<S: AsRef<str>>(url: &str, ignored_params: &[S]) -> String {
+    let url = url.trim();
+    let url = url.split('#').next().unwrap_or_default();
+    let url = match url.find("://") {
+        Some(i)
+            if url[..i]
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+.-".contains(c)) =>
+        {
+            &url[i + 3..]
+        }
+        _ => url,
+    };
+
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let (host, path) = path.split_at(path.find('/').unwrap_or(path.len()));
+
+    let params = query
+        .split('&')
+        .filter(|param| {
+            let key = param.split('=').next().unwrap_or_default();
+
+            !key.is_empty() && !ignored_params.iter().any(|ignored| ignored.as_ref() == key)
+        })
+        .collect::<Vec<&str>>();
+
+    let mut normalised = host.to_lowercase() + path.trim_end_matches('/');
+
+    if !params.is_empty() {
+        normalised.push('?');
+        normalised.push_str(&params.join("&"));
+    }
+
+    normalised
+}
+
+/// The lower-cased host of a URL, without any credentials or port.
+pub fn url_host(url: &str) -> Option<String> {
+    let (_, rest) = url.trim().split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority.rsplit('@').next().unwrap_or_default();
+    let host = match host.find(']') {
+        // IPv6 addresses contain colons of their own
+        Some(i) if host.starts_with('[') => &host[..=i],
+        _ => host.split(':').next().unwrap_or_default(),
+    };
+
+    if host.is_empty() {
+        None
+    } else {
+        Some(host.to_lowercase())
+    }
+}
+
+/// Whether a URL's host is `host`, or optionally any subdomain of it.
+pub fn is_on_host(url: &str, host: &str, include_subdomains: bool) -> bool {
+    let host = host.trim().trim_end_matches('.').to_lowercase();
+
+    url_host(url).is_some_and(|url_host| {
+        url_host == host
+            || (include_subdomains
+                && url_host
+                    .strip_suffix(&host)
+                    .is_some_and(|sub| sub.ends_with('.')))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalise_url() {
+        assert_eq!(
+            normalise_url("https://GitHub.com/samhh/", TRACKING_PARAMS),
+            "github.com/samhh",
+        );
+
+        assert_eq!(
+            normalise_url("http://github.com/samhh#readme", TRACKING_PARAMS),
+            normalise_url("https://github.com/samhh", TRACKING_PARAMS),
+        );
+
+        assert_eq!(
+            normalise_url(
+                "https://example.com/a/?utm_source=x&page=2&fbclid=y",
+                TRACKING_PARAMS
+            ),
+            "example.com/a?page=2",
+        );
+
+        assert_eq!(
+            normalise_url("https://example.com/?page=2", &["page"]),
+            "example.com",
+        );
+
+        // Paths are case-sensitive
+        assert_ne!(
+            normalise_url("https://example.com/A", TRACKING_PARAMS),
+            normalise_url("https://example.com/a", TRACKING_PARAMS),
+        );
+
+        assert_eq!(
+            normalise_url("example.com/", TRACKING_PARAMS),
+            "example.com"
+        );
+    }
+
+    #[test]
+    fn test_url_host() {
+        assert_eq!(
+            url_host("https://user@Docs.RS:443/x?y#z"),
+            Some(String::from("docs.rs"))
+        );
+        assert_eq!(url_host("http://[::1]:8080/"), Some(String::from("[::1]")));
+        assert_eq!(
+            url_host("https://example.com?q"),
+            Some(String::from("example.com"))
+        );
+        assert_eq!(url_host("example.com/a"), None);
+    }
+
+    #[test]
+    fn test_is_on_host() {
+        assert!(is_on_host("https://github.com/samhh", "GitHub.com", false));
+        assert!(!is_on_host(
+            "https://gist.github.com/x",
+            "github.com",
+            false
+        ));
+        assert!(is_on_host("https://gist.github.com/x", "github.com", true));
+        assert!(!is_on_host("https://notgithub.com/", "github.com", true));
+    }
+}
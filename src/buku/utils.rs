@@ -7,10 +7,11 @@ fn var_path(env_var: &str) -> Option<PathBuf> {
     var(env_var).map(PathBuf::from).ok()
 }
 
-/// Determine path to database from environment variables.
+/// Determine path at which Buku expects its database from environment
+/// variables, regardless of whether it exists.
 // Nota bene that this must exactly match the logic of Buku's internal
 // `get_default_dbdir` function.
-pub fn get_db_path() -> Result<PathBuf, IoError> {
+pub fn get_default_db_path() -> Result<PathBuf, IoError> {
     let dir = match TARGET_OS {
         OS::Windows => var_path("APPDATA"),
         _ => var_path("XDG_DATA_HOME")
@@ -19,6 +20,12 @@ pub fn get_db_path() -> Result<PathBuf, IoError> {
     };
 
     dir.map(|data_path| data_path.join("buku/bookmarks.db"))
+        .ok_or_else(|| IoError::new(IoErrorKind::NotFound, "Failed to find data directory."))
+}
+
+/// Determine path to existing database from environment variables.
+pub fn get_db_path() -> Result<PathBuf, IoError> {
+    Some(get_default_db_path()?)
         .filter(|full_path| full_path.is_file())
         .ok_or_else(|| IoError::new(IoErrorKind::NotFound, "Failed to find Buku database."))
 }
@@ -1,18 +1,34 @@
-use crate::buku::types::BookmarkId;
+use crate::buku::tags::TagQuery;
+use crate::buku::types::{BookmarkId, ListOptions, SortKey, Tags};
 use crate::manifest::paths::Browser;
 use clap::{crate_authors, crate_name, crate_version, App, Arg, Error as ClapError};
 
 pub enum Argument {
     /// The second piece of data is an optional custom installation dir.
     InstallBrowserHost(Browser, Option<String>),
-    ListBookmarks,
+    InitDatabase,
+    CheckDatabase,
+    ListBookmarks(ListOptions),
     OpenBookmarks(Vec<BookmarkId>),
+    SearchTags(TagQuery),
+    /// The host, and whether to include its subdomains.
+    SearchHost(String, bool),
+    SearchUrlPrefix(String),
+    ListTags,
+    /// Tags to rename, and the tag to rename them to.
+    RenameTags(Vec<String>, String),
+    DeleteTags(Vec<String>),
+    /// Serve native messaging from sample bookmarks held in memory.
+    Demo,
 }
 
 #[derive(Debug)]
 pub enum CliError {
     Clap(ClapError),
     BookmarkIdsParseFailed,
+    TagQueryParseFailed,
+    LimitParseFailed,
+    RenameTargetEmpty,
 }
 
 /// Initialises the CLI interface and determines if the user explicitly passed
@@ -29,8 +45,21 @@ pub fn init() -> Result<Option<Argument>, CliError> {
     let vivaldi_arg = "install-vivaldi";
     let edge_arg = "install-edge";
     let dir_arg = "install-dir";
+    let init_db_arg = "init-db";
+    let check_db_arg = "check-db";
     let list_arg = "list";
+    let sort_arg = "sort";
+    let desc_arg = "desc";
+    let limit_arg = "limit";
     let open_arg = "open";
+    let stag_arg = "stag";
+    let host_arg = "host";
+    let subdomains_arg = "subdomains";
+    let prefix_arg = "prefix";
+    let tags_arg = "tags";
+    let rename_tag_arg = "rename-tag";
+    let delete_tag_arg = "delete-tag";
+    let demo_arg = "demo";
 
     let matches = App::new(crate_name!())
         .version(crate_version!())
@@ -78,12 +107,45 @@ pub fn init() -> Result<Option<Argument>, CliError> {
                 .takes_value(true)
                 .value_name("DIR"),
         )
+        .arg(
+            Arg::new(init_db_arg)
+                .long("--init-db")
+                .about("Create a Buku database if one doesn't already exist"),
+        )
+        .arg(
+            Arg::new(check_db_arg)
+                .long("--check-db")
+                .about("Report any bookmarks that can't be read from the database"),
+        )
         .arg(
             Arg::new(list_arg)
                 .short('l')
                 .long("--list")
                 .about("Print all bookmarks in a list to stdout"),
         )
+        .arg(
+            Arg::new(sort_arg)
+                .long("--sort")
+                .about("Sort bookmarks passed to --list")
+                .takes_value(true)
+                .possible_values(["id", "title", "url", "domain", "tag-count"])
+                .value_name("KEY")
+                .requires(list_arg),
+        )
+        .arg(
+            Arg::new(desc_arg)
+                .long("--desc")
+                .about("Sort bookmarks passed to --list in descending order")
+                .requires(list_arg),
+        )
+        .arg(
+            Arg::new(limit_arg)
+                .long("--limit")
+                .about("Print at most this many bookmarks with --list")
+                .takes_value(true)
+                .value_name("N")
+                .requires(list_arg),
+        )
         .arg(
             Arg::new(open_arg)
                 .short('o')
@@ -93,9 +155,67 @@ pub fn init() -> Result<Option<Argument>, CliError> {
                 .value_delimiter(',')
                 .value_name("ID[,ID]"),
         )
+        .arg(
+            Arg::new(stag_arg)
+                .long("--stag")
+                .about("Print bookmarks matching a tag expression")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .value_name("EXPR"),
+        )
+        .arg(
+            Arg::new(host_arg)
+                .long("--host")
+                .about("Print bookmarks on a host")
+                .takes_value(true)
+                .value_name("HOST"),
+        )
+        .arg(
+            Arg::new(subdomains_arg)
+                .long("--subdomains")
+                .about("Include subdomains of the host passed to --host")
+                .requires(host_arg),
+        )
+        .arg(
+            Arg::new(prefix_arg)
+                .long("--prefix")
+                .about("Print bookmarks whose URLs begin with a prefix")
+                .takes_value(true)
+                .value_name("URL"),
+        )
+        .arg(
+            Arg::new(tags_arg)
+                .long("--tags")
+                .about("Print all tags with their bookmark counts to stdout"),
+        )
+        .arg(
+            Arg::new(rename_tag_arg)
+                .long("--rename-tag")
+                .about("Rename tag(s) on all bookmarks, merging them if several")
+                .takes_value(true)
+                .number_of_values(2)
+                .value_names(&["OLD[,OLD]", "NEW"]),
+        )
+        .arg(
+            Arg::new(delete_tag_arg)
+                .long("--delete-tag")
+                .about("Remove tag(s) from all bookmarks")
+                .takes_value(true)
+                .value_delimiter(',')
+                .value_name("TAG[,TAG]"),
+        )
+        .arg(
+            Arg::new(demo_arg)
+                .long("--demo")
+                .about("Serve sample bookmarks without touching the Buku database"),
+        )
         .try_get_matches()
         .map_err(CliError::Clap)?;
 
+    if matches.is_present(demo_arg) {
+        return Ok(Some(Argument::Demo));
+    }
+
     if let Some(vals) = matches.values_of(open_arg) {
         let mut ids = Vec::with_capacity(vals.len());
 
@@ -106,8 +226,74 @@ pub fn init() -> Result<Option<Argument>, CliError> {
         return Ok(Some(Argument::OpenBookmarks(ids)));
     }
 
+    if let Some(expr) = matches.value_of(stag_arg) {
+        let query = TagQuery::parse(expr).map_err(|_| CliError::TagQueryParseFailed)?;
+
+        return Ok(Some(Argument::SearchTags(query)));
+    }
+
+    if let Some(host) = matches.value_of(host_arg) {
+        return Ok(Some(Argument::SearchHost(
+            String::from(host),
+            matches.is_present(subdomains_arg),
+        )));
+    }
+
+    if let Some(prefix) = matches.value_of(prefix_arg) {
+        return Ok(Some(Argument::SearchUrlPrefix(String::from(prefix))));
+    }
+
+    if let Some(mut vals) = matches.values_of(rename_tag_arg) {
+        // Clap guarantees exactly two values
+        let from = vals.next().unwrap_or_default();
+        let to = vals.next().unwrap_or_default();
+
+        // Renaming to nothing would silently delete the tag(s)
+        if Tags::parse(to).is_empty() {
+            return Err(CliError::RenameTargetEmpty);
+        }
+
+        return Ok(Some(Argument::RenameTags(
+            from.split(',').map(String::from).collect(),
+            String::from(to),
+        )));
+    }
+
+    if let Some(vals) = matches.values_of(delete_tag_arg) {
+        return Ok(Some(Argument::DeleteTags(vals.map(String::from).collect())));
+    }
+
+    if matches.is_present(tags_arg) {
+        return Ok(Some(Argument::ListTags));
+    }
+
+    if matches.is_present(init_db_arg) {
+        return Ok(Some(Argument::InitDatabase));
+    }
+
+    if matches.is_present(check_db_arg) {
+        return Ok(Some(Argument::CheckDatabase));
+    }
+
     if matches.is_present(list_arg) {
-        return Ok(Some(Argument::ListBookmarks));
+        let sort = match matches.value_of(sort_arg) {
+            Some("title") => SortKey::Title,
+            Some("url") => SortKey::Url,
+            Some("domain") => SortKey::Domain,
+            Some("tag-count") => SortKey::TagCount,
+            // Clap guarantees no other values
+            _ => SortKey::Id,
+        };
+        let limit = match matches.value_of(limit_arg) {
+            Some(limit) => Some(limit.parse().map_err(|_| CliError::LimitParseFailed)?),
+            None => None,
+        };
+
+        return Ok(Some(Argument::ListBookmarks(ListOptions {
+            sort,
+            descending: matches.is_present(desc_arg),
+            limit,
+        })));
     }
 
     let dir = matches.value_of(dir_arg).map(String::from);
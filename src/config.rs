@@ -2,3 +2,13 @@
 pub const NAME: &str = "com.samhh.bukubrow";
 
 pub const DESC: &str = "Bukubrow is a WebExtension for Buku, a command-line bookmark manager. This is the corresponding host that facilitates interfacing with the Buku database via native messaging.";
+
+/// Environment variable overriding how long to wait, in milliseconds, for
+/// another connection such as the Buku CLI to release a lock on the database.
+pub const BUSY_TIMEOUT_ENV: &str = "BUKUBROW_BUSY_TIMEOUT_MS";
+pub const DEFAULT_BUSY_TIMEOUT_MS: u64 = 5000;
+
+/// Environment variable overriding how many times to retry a write that's
+/// still locked out after the busy timeout.
+pub const LOCK_RETRIES_ENV: &str = "BUKUBROW_LOCK_RETRIES";
+pub const DEFAULT_LOCK_RETRIES: u32 = 2;
@@ -10,22 +10,99 @@ mod manifest;
 mod native_messaging;
 mod server;
 
-use crate::buku::database::{BukuDatabase, SqliteDatabase};
-use crate::buku::utils::get_db_path;
+use crate::buku::database::{BatchMode, BukuDatabase, DbError, LockConfig, SqliteDatabase};
+use crate::buku::memory::InMemoryDatabase;
+use crate::buku::types::{ConflictPolicy, ListOptions, Tags, UnsavedBookmark};
+use crate::buku::utils::{get_db_path, get_default_db_path};
 use crate::cli::{exit_with_stdout_err, Argument, CliError};
 use crate::manifest::installer::install_manifest;
 use crate::native_messaging::NativeMessagingError;
 use crate::server::{map_init_err_friendly_msg, InitError, Server};
 use clap::ErrorKind;
+use std::fs::create_dir_all;
 use std::path::PathBuf;
 
-fn main() {
-    let db = get_db_path()
-        .map_err(|_| InitError::FailedToLocateBukuDatabase)
-        .and_then(|path| {
-            SqliteDatabase::new(&path).map_err(|_| InitError::FailedToAccessBukuDatabase)
-        });
+/// Create a Buku database at the location Buku expects to find it.
+fn create_db() -> Result<SqliteDatabase, InitError> {
+    let path = get_default_db_path().map_err(|_| InitError::FailedToLocateBukuDatabase)?;
+
+    if let Some(dir) = path.parent() {
+        create_dir_all(dir).map_err(|_| InitError::FailedToCreateBukuDatabase)?;
+    }
+
+    SqliteDatabase::create(&path, LockConfig::from_env()).map_err(|err| match err {
+        DbError::IncompatibleSchema(_) => InitError::IncompatibleBukuDatabase,
+        _ => InitError::FailedToCreateBukuDatabase,
+    })
+}
+
+/// A database of sample bookmarks for demo mode, afresh each session.
+fn demo_db() -> Result<InMemoryDatabase, InitError> {
+    let db = InMemoryDatabase::default();
+    let sample = |url: &str, metadata: &str, tags: &str, desc: &str| UnsavedBookmark {
+        url: String::from(url),
+        metadata: String::from(metadata),
+        tags: Tags::parse(tags),
+        desc: String::from(desc),
+        flags: 0,
+    };
+
+    let added = db
+        .add_bookmarks(
+            &[
+                sample(
+                    "https://github.com/jarun/buku",
+                    "Buku",
+                    "bookmarks,cli",
+                    "Personal mini-web in text.",
+                ),
+                sample(
+                    "https://github.com/samhh/bukubrow-webext",
+                    "Bukubrow",
+                    "bookmarks,browser",
+                    "WebExtension for Buku.",
+                ),
+                sample(
+                    "https://www.rust-lang.org/",
+                    "Rust Programming Language",
+                    "rust,programming",
+                    "A language empowering everyone to build reliable and efficient software.",
+                ),
+                sample(
+                    "https://doc.rust-lang.org/book/",
+                    "The Rust Programming Language",
+                    "rust,programming,docs",
+                    "",
+                ),
+                sample(
+                    "https://www.sqlite.org/docs.html",
+                    "SQLite Documentation",
+                    "docs,databases",
+                    "",
+                ),
+            ],
+            ConflictPolicy::Reject,
+            BatchMode::Atomic,
+        )
+        .map_err(|_| InitError::FailedToCreateBukuDatabase)?;
+
+    // The batch is atomic, but a failure is reported per bookmark
+    if added.iter().any(Result::is_err) {
+        return Err(InitError::FailedToCreateBukuDatabase);
+    }
+
+    Ok(db)
+}
+
+/// Serve native messaging until the WebExtension disconnects.
+fn listen<T: BukuDatabase>(server: Server<T>) -> ! {
+    match server.listen() {
+        Ok(_) | Err(NativeMessagingError::NoMoreInput) => std::process::exit(0),
+        _ => std::process::exit(1),
+    }
+}
 
+fn main() {
     // Native messaging can provide its own arguments we don't care about, so
     // ignore any unrecognised arguments
     let recognised_arg = cli::init().unwrap_or_else(|err| match err {
@@ -36,8 +113,41 @@ fn main() {
         CliError::BookmarkIdsParseFailed => {
             exit_with_stdout_err("Failed to parse bookmark ID(s).");
         }
+        CliError::TagQueryParseFailed => {
+            exit_with_stdout_err("Failed to parse tag expression.");
+        }
+        CliError::LimitParseFailed => {
+            exit_with_stdout_err("Failed to parse limit.");
+        }
+        CliError::RenameTargetEmpty => {
+            exit_with_stdout_err("Tag to rename to must not be empty.");
+        }
     });
 
+    // Demo mode never touches the Buku database, even to create one
+    if let Some(Argument::Demo) = recognised_arg {
+        listen(Server::new(demo_db(), demo_db));
+    }
+
+    let db = get_db_path()
+        .map_err(|_| InitError::FailedToLocateBukuDatabase)
+        .and_then(|path| {
+            SqliteDatabase::new(&path, LockConfig::from_env()).map_err(|err| match err {
+                DbError::IncompatibleSchema(_) => InitError::IncompatibleBukuDatabase,
+                _ => InitError::FailedToAccessBukuDatabase,
+            })
+        });
+
+    // Never create a database where one exists but couldn't be accessed
+    let db = match db {
+        Err(InitError::FailedToLocateBukuDatabase)
+            if matches!(recognised_arg, Some(Argument::InitDatabase)) =>
+        {
+            create_db()
+        }
+        db => db,
+    };
+
     // Only continue to native messaging if no recognised flags are found
     if let Some(arg) = recognised_arg {
         match db {
@@ -60,9 +170,31 @@ fn main() {
                         }
                     };
                 }
-                Argument::ListBookmarks => match db.get_all_bookmarks() {
+                Argument::InitDatabase => println!("Buku database is ready to use."),
+                Argument::CheckDatabase => match db.get_all_bookmarks(&ListOptions::default()) {
+                    Ok(bms) if bms.skipped.is_empty() => {
+                        println!("All {} bookmark(s) are readable.", bms.bookmarks.len());
+                    }
                     Ok(bms) => {
-                        for bm in bms {
+                        for row in &bms.skipped {
+                            match row.id {
+                                Some(id) => println!("{} {}", id, row.reason),
+                                None => println!("? {}", row.reason),
+                            }
+                        }
+
+                        exit_with_stdout_err(format!(
+                            "{} bookmark(s) could not be read.",
+                            bms.skipped.len()
+                        ));
+                    }
+                    Err(_) => {
+                        exit_with_stdout_err("Failed to fetch bookmarks from database.");
+                    }
+                },
+                Argument::ListBookmarks(options) => match db.get_all_bookmarks(&options) {
+                    Ok(bms) => {
+                        for bm in bms.bookmarks {
                             println!("{} {}", bm.id, bm.metadata);
                         }
                     }
@@ -70,9 +202,71 @@ fn main() {
                         exit_with_stdout_err("Failed to fetch bookmarks from database.");
                     }
                 },
+                Argument::SearchTags(query) => {
+                    match db.get_bookmarks_by_tags(&query, &ListOptions::default()) {
+                        Ok(bms) => {
+                            for bm in bms.bookmarks {
+                                println!("{} {}", bm.id, bm.metadata);
+                            }
+                        }
+                        Err(_) => {
+                            exit_with_stdout_err("Failed to fetch bookmarks from database.");
+                        }
+                    }
+                }
+                Argument::SearchHost(host, include_subdomains) => {
+                    match db.get_bookmarks_by_host(
+                        &host,
+                        include_subdomains,
+                        &ListOptions::default(),
+                    ) {
+                        Ok(bms) => {
+                            for bm in bms.bookmarks {
+                                println!("{} {}", bm.id, bm.metadata);
+                            }
+                        }
+                        Err(_) => {
+                            exit_with_stdout_err("Failed to fetch bookmarks from database.");
+                        }
+                    }
+                }
+                Argument::SearchUrlPrefix(prefix) => {
+                    match db.get_bookmarks_by_url_prefix(&prefix, &ListOptions::default()) {
+                        Ok(bms) => {
+                            for bm in bms.bookmarks {
+                                println!("{} {}", bm.id, bm.metadata);
+                            }
+                        }
+                        Err(_) => {
+                            exit_with_stdout_err("Failed to fetch bookmarks from database.");
+                        }
+                    }
+                }
+                Argument::ListTags => match db.get_tags() {
+                    Ok(tags) => {
+                        for tag in tags.tags {
+                            println!("{} ({})", tag.tag, tag.count);
+                        }
+                    }
+                    Err(_) => {
+                        exit_with_stdout_err("Failed to fetch tags from database.");
+                    }
+                },
+                Argument::RenameTags(from, to) => match db.rename_tags(&from, &to) {
+                    Ok(updated) => println!("Updated {} bookmark(s).", updated),
+                    Err(err) => {
+                        exit_with_stdout_err(format!("Failed to rename tag(s): {}", err));
+                    }
+                },
+                Argument::DeleteTags(tags) => match db.delete_tags(&tags) {
+                    Ok(updated) => println!("Updated {} bookmark(s).", updated),
+                    Err(err) => {
+                        exit_with_stdout_err(format!("Failed to delete tag(s): {}", err));
+                    }
+                },
                 Argument::OpenBookmarks(ids) => match db.get_bookmarks_by_id(ids) {
                     Ok(bms) => {
-                        for bm in bms {
+                        for bm in bms.bookmarks {
                             if webbrowser::open(&bm.url).is_err() {
                                 exit_with_stdout_err("Failed to open bookmark in web browser.");
                             }
@@ -82,6 +276,7 @@ fn main() {
                         exit_with_stdout_err("Failed to fetch selected bookmarks from database.");
                     }
                 },
+                Argument::Demo => unreachable!("Demo mode is served above"),
             },
             Err(err) => {
                 exit_with_stdout_err(map_init_err_friendly_msg(&err));
@@ -94,10 +289,5 @@ fn main() {
     // No installation arguments supplied, proceed with native messaging. Do not
     // exit if cannot find or access Buku database, instead allow server to
     // communicate that. This is an asynchronous call.
-    let res = Server::new(db).listen();
-
-    match res {
-        Ok(_) | Err(NativeMessagingError::NoMoreInput) => std::process::exit(0),
-        _ => std::process::exit(1),
-    }
+    listen(Server::new(db, create_db));
 }
@@ -1,14 +1,31 @@
-use crate::buku::database::BukuDatabase;
-use crate::buku::types::{BookmarkId, SavedBookmark, UnsavedBookmark};
+use crate::buku::changes::{fingerprint, Changes, Snapshot};
+use crate::buku::database::{compose_id_moves, BatchMode, BukuDatabase, DbError};
+use crate::buku::tags::TagQuery;
+use crate::buku::types::{
+    BookmarkField, BookmarkId, ConflictPolicy, ListOptions, SavedBookmark, SearchMode, Tags,
+    UnsavedBookmark, UrlMatch,
+};
+use crate::buku::urls::TRACKING_PARAMS;
 use crate::native_messaging::{read_input, write_output, NativeMessagingError, ONE_MEGABYTE_BYTES};
 use clap::crate_version;
+use serde::Serialize;
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::io;
+use std::ops::Range;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 /// If the server is not provided with a valid database, it needs to know why
 /// so that it can communicate that.
+#[allow(clippy::enum_variant_names)]
 pub enum InitError {
     FailedToLocateBukuDatabase,
     FailedToAccessBukuDatabase,
+    FailedToCreateBukuDatabase,
+    IncompatibleBukuDatabase,
 }
 
 #[derive(Debug, PartialEq)]
@@ -17,22 +34,8 @@ enum BookmarksSplitError {
     Unknown,
 }
 
-enum BookmarksSplitOffset {
-    Offset(usize),
-    None,
-}
-
-impl BookmarksSplitOffset {
-    fn unwrap_or(&self, v: usize) -> usize {
-        match self {
-            BookmarksSplitOffset::Offset(offset) => *offset,
-            BookmarksSplitOffset::None => v,
-        }
-    }
-}
-
 #[allow(dead_code)]
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy)]
 enum BookmarksSplitPayloadSize {
     Limited(usize),
     Unlimited,
@@ -42,11 +45,108 @@ pub fn map_init_err_friendly_msg(err: &InitError) -> &'static str {
     match err {
         InitError::FailedToLocateBukuDatabase => "Failed to locate Buku database.",
         InitError::FailedToAccessBukuDatabase => "Failed to access Buku database.",
+        InitError::FailedToCreateBukuDatabase => "Failed to create Buku database.",
+        InitError::IncompatibleBukuDatabase => {
+            "Database is not a Buku database or is from an incompatible version of Buku."
+        }
+    }
+}
+
+/// Machine-readable codes for database errors, for the WebExtension to act on.
+pub fn map_db_err_code(err: &DbError) -> &'static str {
+    match err {
+        DbError::NotFound(_) => "notFound",
+        DbError::DuplicateUrl(_) => "duplicateUrl",
+        DbError::ConstraintViolation => "constraintViolation",
+        DbError::Locked => "databaseLocked",
+        DbError::RolledBack => "rolledBack",
+        DbError::IncompatibleSchema(_) => "incompatibleSchema",
+        DbError::SyncTokenExpired => "syncTokenExpired",
+        DbError::Sqlite(_) => "unknown",
     }
 }
 
 type Json = serde_json::Value;
 
+/// Batches are atomic unless the request opts in to best effort.
+fn batch_mode(best_effort: bool) -> BatchMode {
+    if best_effort {
+        BatchMode::BestEffort
+    } else {
+        BatchMode::Atomic
+    }
+}
+
+/// Serialise tags as an array of strings rather than in Buku's delimited
+/// string format.
+const CAPABILITY_TAGS_ARRAY: &str = "tagsArray";
+
+/// Subscribe to unsolicited messages describing changes made to the database
+/// by other programs, such as the Buku CLI, for the rest of the session.
+const CAPABILITY_CHANGE_EVENTS: &str = "changeEvents";
+
+/// Every capability the host supports, as advertised by `OPTIONS`.
+const CAPABILITIES: &[&str] = &[CAPABILITY_TAGS_ARRAY, CAPABILITY_CHANGE_EVENTS];
+
+/// Optional protocol features that a request has opted in to.
+#[derive(Default)]
+struct Capabilities {
+    tags_array: bool,
+    change_events: bool,
+}
+
+/// How often to check for changes to the database whilst subscribed.
+const CHANGE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The fingerprint of every bookmark as of a version of the database, as
+/// presented per a view.
+struct Fingerprint {
+    version: i64,
+    view: u64,
+    value: String,
+}
+
+/// How long a listing is held for its remaining pages to be requested.
+const LISTING_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// A listing held between requests for its pages, so that every page is
+/// consistent with the first.
+struct Listing {
+    id: u64,
+    created: Instant,
+    /// Each bookmark as serialised once upfront, rather than as a tree of
+    /// values, to be measured and then joined into pages.
+    bookmarks: Vec<String>,
+    /// The range of bookmarks in each page, sized upfront.
+    pages: Vec<Range<usize>>,
+    /// Fields beyond the bookmarks to include in every page.
+    extra: serde_json::Map<String, Json>,
+}
+
+/// A hash of how a listing's bookmarks are presented, as they're sorted and
+/// projected, so that a fingerprint is never shared by listings that differ
+/// only in presentation.
+fn listing_view(data: &RequestDataGet, caps: &Capabilities) -> u64 {
+    let mut hasher = DefaultHasher::new();
+
+    data.fields.hash(&mut hasher);
+    data.list.hash(&mut hasher);
+    caps.tags_array.hash(&mut hasher);
+
+    hasher.finish()
+}
+
+/// The cursor for a page of a listing.
+fn cursor(listing_id: u64, page: usize) -> String {
+    format!("{}.{}", listing_id, page)
+}
+
+/// The state of the database as of the last check for changes.
+struct Watch {
+    version: i64,
+    snapshot: Snapshot,
+}
+
 #[derive(Debug, PartialEq)]
 enum Method {
     Get,
@@ -58,19 +158,69 @@ enum Method {
     None,
 }
 
+/// The resource a request operates upon. Requests without a resource operate
+/// upon bookmarks.
+#[derive(Debug, PartialEq)]
+enum Resource {
+    Bookmarks,
+    Tags,
+    Lookup,
+    Database,
+    Unknown,
+}
+
 #[derive(Deserialize)]
 struct RequestMethod {
     method: String,
 }
 
+#[derive(Deserialize)]
+struct RequestResource {
+    resource: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RequestCapabilities {
+    capabilities: Vec<String>,
+}
+
 #[derive(Deserialize)]
 struct RequestData<T> {
     data: T,
 }
 
 #[derive(Deserialize)]
+struct RequestSearch {
+    keywords: Vec<String>,
+    #[serde(default)]
+    mode: SearchMode,
+}
+
+#[derive(Deserialize, Default)]
 struct RequestDataGet {
-    offset: Option<usize>,
+    /// The next page of a previous listing, in place of a new listing.
+    cursor: Option<String>,
+    /// No longer supported, but recognised so that clients still paging by
+    /// offset fail rather than receive the first page forever.
+    offset: Option<Json>,
+    search: Option<RequestSearch>,
+    tag_query: Option<String>,
+    host: Option<String>,
+    #[serde(default)]
+    include_subdomains: bool,
+    url_prefix: Option<String>,
+    /// Begin syncing, including a token for the next sync.
+    #[serde(default)]
+    sync: bool,
+    /// Only what's changed since the sync token was issued.
+    since: Option<String>,
+    /// The fingerprint of a previous response, to skip sending the same
+    /// bookmarks again.
+    if_none_match: Option<String>,
+    /// The fields of each bookmark to include, in place of every field.
+    fields: Option<Vec<BookmarkField>>,
+    #[serde(flatten)]
+    list: ListOptions,
 }
 
 type GetRequest = RequestData<Option<RequestDataGet>>;
@@ -78,6 +228,10 @@ type GetRequest = RequestData<Option<RequestDataGet>>;
 #[derive(Deserialize)]
 struct RequestDataPost {
     bookmarks: Vec<UnsavedBookmark>,
+    #[serde(default)]
+    on_conflict: ConflictPolicy,
+    #[serde(default)]
+    best_effort: bool,
 }
 
 type PostRequest = RequestData<RequestDataPost>;
@@ -85,6 +239,8 @@ type PostRequest = RequestData<RequestDataPost>;
 #[derive(Deserialize)]
 struct RequestDataPut {
     bookmarks: Vec<SavedBookmark>,
+    #[serde(default)]
+    best_effort: bool,
 }
 
 type PutRequest = RequestData<RequestDataPut>;
@@ -92,35 +248,163 @@ type PutRequest = RequestData<RequestDataPut>;
 #[derive(Deserialize)]
 struct RequestDataDelete {
     bookmark_ids: Vec<BookmarkId>,
+    /// Leave holes in the IDs of the remaining bookmarks rather than
+    /// compacting them as Buku does.
+    #[serde(default)]
+    preserve_ids: bool,
+    #[serde(default)]
+    best_effort: bool,
 }
 
 type DeleteRequest = RequestData<RequestDataDelete>;
 
+#[derive(Deserialize)]
+struct RequestDataPutTags {
+    from: Vec<String>,
+    to: String,
+}
+
+type PutTagsRequest = RequestData<RequestDataPutTags>;
+
+#[derive(Deserialize)]
+struct RequestDataDeleteTags {
+    tags: Vec<String>,
+}
+
+type DeleteTagsRequest = RequestData<RequestDataDeleteTags>;
+
+#[derive(Deserialize)]
+struct RequestDataGetLookup {
+    urls: Vec<String>,
+    #[serde(default)]
+    normalise: bool,
+    /// Query parameters to ignore when normalising, in place of the default
+    /// tracking parameters.
+    ignored_params: Option<Vec<String>>,
+    fields: Option<Vec<BookmarkField>>,
+}
+
+type GetLookupRequest = RequestData<RequestDataGetLookup>;
+
 pub struct Server<T> {
-    db: Result<T, InitError>,
+    db: RefCell<Result<T, InitError>>,
+    /// Creates a database for when one could not be located.
+    create_db: fn() -> Result<T, InitError>,
+    /// Whether the WebExtension has subscribed to change events.
+    subscribed: Cell<bool>,
+    watch: RefCell<Option<Watch>>,
+    /// Forgotten upon any write via the host, which doesn't change the version
+    /// of the database as seen by the host.
+    fingerprint: RefCell<Option<Fingerprint>>,
+    /// The listing with pages yet to be requested, superseded by the next.
+    listing: RefCell<Option<Listing>>,
+    /// The ID of the last listing, initially unique to this session.
+    listing_id: Cell<u64>,
 }
 
 impl<T: BukuDatabase> Server<T> {
-    pub fn new(db: Result<T, InitError>) -> Self {
-        Self { db }
+    pub fn new(db: Result<T, InitError>, create_db: fn() -> Result<T, InitError>) -> Self {
+        Self {
+            db: RefCell::new(db),
+            create_db,
+            subscribed: Cell::new(false),
+            watch: RefCell::new(None),
+            fingerprint: RefCell::new(None),
+            listing: RefCell::new(None),
+            listing_id: Cell::new(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_nanos() as u64),
+            ),
+        }
     }
 
-    // Listen for native messages from WebExtension in a loop
+    // Listen for native messages from WebExtension in a loop, checking for
+    // changes to push to it in between
     pub fn listen(&self) -> Result<(), NativeMessagingError> {
-        loop {
+        let (sender, receiver) = channel();
+
+        // Input is read on its own thread so that waiting for it doesn't block
+        // checking for changes
+        thread::spawn(move || loop {
             match read_input(io::stdin()) {
+                Ok(payload) => {
+                    if sender.send(payload).is_err() {
+                        break;
+                    }
+                }
+                Err(NativeMessagingError::NoMoreInput) => break,
+                Err(_) => {}
+            }
+        });
+
+        loop {
+            match receiver.recv_timeout(CHANGE_POLL_INTERVAL) {
                 Ok(payload) => {
                     write_output(io::stdout(), &self.router(payload))?;
                 }
-                Err(err) => {
-                    if let NativeMessagingError::NoMoreInput = err {
-                        break Err(err);
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Some(event) = self.poll_changes() {
+                        write_output(io::stdout(), &event)?;
                     }
                 }
+                Err(RecvTimeoutError::Disconnected) => {
+                    break Err(NativeMessagingError::NoMoreInput);
+                }
             }
         }
     }
 
+    /// Check whether the database has been changed by another program since
+    /// the last check, returning an event describing any changes. Changes made
+    /// via the host itself are included in the next event, if any.
+    fn poll_changes(&self) -> Option<Json> {
+        if !self.subscribed.get() {
+            return None;
+        }
+
+        let db = self.db.borrow();
+        let db = db.as_ref().ok()?;
+        let version = db.data_version().ok()?;
+        let mut watch = self.watch.borrow_mut();
+
+        if matches!(&*watch, Some(prev) if prev.version == version) {
+            return None;
+        }
+
+        let snapshot = Snapshot::new(
+            &db.get_all_bookmarks(&ListOptions::default())
+                .ok()?
+                .bookmarks,
+        );
+        let changes = watch.as_ref().map(|prev| prev.snapshot.diff(&snapshot));
+        *watch = Some(Watch { version, snapshot });
+
+        changes
+            .filter(|changes| !changes.is_empty())
+            .map(|changes| self.change_event_json(&changes))
+    }
+
+    fn change_event_json(&self, changes: &Changes) -> Json {
+        let event = json!({
+            "event": "changed",
+            "added": changes.added,
+            "updated": changes.updated,
+            "deleted": changes.deleted,
+        });
+
+        // Too many changes to list, for example following an import
+        let size = serde_json::to_vec(&event).map_or(usize::MAX, |bytes| bytes.len());
+        if size > *ONE_MEGABYTE_BYTES {
+            return json!({
+                "event": "changed",
+                "resync": true,
+            });
+        }
+
+        event
+    }
+
     fn method_deserializer(&self, payload: Json) -> Method {
         if let Ok(RequestMethod { method }) = serde_json::from_value(payload) {
             match method.as_ref() {
@@ -136,100 +420,506 @@ impl<T: BukuDatabase> Server<T> {
         }
     }
 
-    fn split_bookmarks_subset(
+    fn resource_deserializer(&self, payload: Json) -> Resource {
+        match serde_json::from_value(payload) {
+            Ok(RequestResource {
+                resource: Some(resource),
+            }) => match resource.as_ref() {
+                "bookmarks" => Resource::Bookmarks,
+                "tags" => Resource::Tags,
+                "lookup" => Resource::Lookup,
+                "database" => Resource::Database,
+                _ => Resource::Unknown,
+            },
+            _ => Resource::Bookmarks,
+        }
+    }
+
+    fn capabilities_deserializer(&self, payload: Json) -> Capabilities {
+        match serde_json::from_value(payload) {
+            Ok(RequestCapabilities { capabilities }) => Capabilities {
+                tags_array: capabilities.iter().any(|c| c == CAPABILITY_TAGS_ARRAY),
+                change_events: capabilities.iter().any(|c| c == CAPABILITY_CHANGE_EVENTS),
+            },
+            Err(_) => Capabilities::default(),
+        }
+    }
+
+    fn page_json<B: Serialize>(&self, bms: &[B], are_more: bool) -> Json {
+        json!({
+            "success": true,
+            "bookmarks": bms,
+            "moreAvailable": are_more,
+        })
+    }
+
+    /// Split bookmarks, of the given serialised sizes, into consecutive pages
+    /// that each fit within the maximum size once wrapped in a response.
+    fn paginate(
         &self,
-        all_bms: &[SavedBookmark],
-        bms_offset: BookmarksSplitOffset,
+        bm_sizes: &[usize],
         max_page_size_bytes: BookmarksSplitPayloadSize,
-    ) -> Result<Json, BookmarksSplitError> {
-        let gen_res = |bms: &[SavedBookmark], are_more: bool| {
-            json!({
+    ) -> Result<Vec<Range<usize>>, BookmarksSplitError> {
+        let max_size = match max_page_size_bytes {
+            BookmarksSplitPayloadSize::Unlimited => usize::MAX,
+            BookmarksSplitPayloadSize::Limited(max_size) => max_size,
+        };
+        let overhead = serde_json::to_vec(&self.page_json::<Json>(&[], false))
+            .map_err(|_| BookmarksSplitError::Unknown)?
+            .len();
+
+        let mut pages = Vec::new();
+        let mut start = 0;
+        let mut size_so_far = overhead;
+
+        for (i, &bm_size) in bm_sizes.iter().enumerate() {
+            let mut new_size_so_far = size_so_far + bm_size + std::cmp::min(i - start, 1); // Comma is 1 byte
+            if new_size_so_far >= max_size && i > start {
+                pages.push(start..i);
+                start = i;
+                new_size_so_far = overhead + bm_size;
+            }
+
+            if new_size_so_far >= max_size {
+                return Err(BookmarksSplitError::BookmarkLargerThanMaxPayloadSize);
+            }
+
+            size_so_far = new_size_so_far;
+        }
+
+        pages.push(start..bm_sizes.len());
+
+        Ok(pages)
+    }
+
+    // Route requests per the resource and method
+    pub fn router(&self, payload: Json) -> Json {
+        let caps = self.capabilities_deserializer(payload.clone());
+        let resource = self.resource_deserializer(payload.clone());
+        let method = self.method_deserializer(payload.clone());
+
+        // A database can be created when there isn't yet one to use
+        if resource == Resource::Database && method == Method::Post {
+            return self.post_database();
+        }
+
+        // Snapshot the database upon subscribing to detect changes against
+        if caps.change_events && !self.subscribed.replace(true) {
+            self.poll_changes();
+        }
+
+        if !matches!(method, Method::Get | Method::Options) {
+            self.fingerprint.replace(None);
+        }
+
+        match &*self.db.borrow() {
+            Ok(db) => match (resource, method) {
+                (_, Method::Options) => self.options(),
+                (_, Method::Unknown) => self.fail_unknown_method(),
+                (_, Method::None) => self.fail_no_method(),
+                (Resource::Unknown, _) => self.fail_unknown_resource(),
+                (Resource::Bookmarks, Method::Get) => serde_json::from_value::<GetRequest>(payload)
+                    .map(|req| self.get(db, &req.data.unwrap_or_default(), &caps))
+                    .unwrap_or_else(|_| self.fail_bad_payload()),
+                (Resource::Bookmarks, Method::Post) => {
+                    serde_json::from_value::<PostRequest>(payload)
+                        .map(|req| {
+                            self.post(
+                                db,
+                                &req.data.bookmarks,
+                                req.data.on_conflict,
+                                batch_mode(req.data.best_effort),
+                                &caps,
+                            )
+                        })
+                        .unwrap_or_else(|_| self.fail_bad_payload())
+                }
+                (Resource::Bookmarks, Method::Put) => serde_json::from_value::<PutRequest>(payload)
+                    .map(|req| self.put(db, &req.data.bookmarks, batch_mode(req.data.best_effort)))
+                    .unwrap_or_else(|_| self.fail_bad_payload()),
+                (Resource::Bookmarks, Method::Delete) => {
+                    serde_json::from_value::<DeleteRequest>(payload)
+                        .map(|req| {
+                            self.delete(
+                                db,
+                                &req.data.bookmark_ids,
+                                !req.data.preserve_ids,
+                                batch_mode(req.data.best_effort),
+                            )
+                        })
+                        .unwrap_or_else(|_| self.fail_bad_payload())
+                }
+                (Resource::Tags, Method::Get) => self.get_tags(db),
+                (Resource::Tags, Method::Put) => serde_json::from_value::<PutTagsRequest>(payload)
+                    .map(|req| self.put_tags(db, &req.data.from, &req.data.to))
+                    .unwrap_or_else(|_| self.fail_bad_payload()),
+                (Resource::Tags, Method::Delete) => {
+                    serde_json::from_value::<DeleteTagsRequest>(payload)
+                        .map(|req| self.delete_tags(db, &req.data.tags))
+                        .unwrap_or_else(|_| self.fail_bad_payload())
+                }
+                (Resource::Tags, Method::Post) => self.fail_unsupported_method(),
+                (Resource::Lookup, Method::Get) => {
+                    serde_json::from_value::<GetLookupRequest>(payload)
+                        .map(|req| self.get_lookup(db, &req.data, &caps))
+                        .unwrap_or_else(|_| self.fail_bad_payload())
+                }
+                (Resource::Lookup, _) => self.fail_unsupported_method(),
+                (Resource::Database, _) => self.fail_unsupported_method(),
+            },
+            Err(err) => self.fail_init_error(err),
+        }
+    }
+
+    fn post_database(&self) -> Json {
+        let mut db = self.db.borrow_mut();
+
+        match &*db {
+            Ok(_) => json!({
                 "success": true,
-                "bookmarks": &bms,
-                "moreAvailable": are_more,
-            })
+                "created": false,
+            }),
+            Err(InitError::FailedToLocateBukuDatabase) => match (self.create_db)() {
+                Ok(created) => {
+                    *db = Ok(created);
+
+                    json!({
+                        "success": true,
+                        "created": true,
+                    })
+                }
+                Err(err) => self.fail_init_error(&err),
+            },
+            // Don't risk clobbering a database that exists but can't be used
+            Err(err) => self.fail_init_error(err),
+        }
+    }
+
+    fn get(&self, db: &T, data: &RequestDataGet, caps: &Capabilities) -> Json {
+        if data.offset.is_some() {
+            return self.fail_offset_unsupported();
+        }
+
+        if let Some(cursor) = &data.cursor {
+            return self.get_next_page(cursor);
+        }
+
+        let filters = [
+            data.search.is_some(),
+            data.tag_query.is_some(),
+            data.host.is_some(),
+            data.url_prefix.is_some(),
+            data.since.is_some() || data.sync,
+        ];
+
+        // Filters are mutually exclusive, as is syncing, which concerns every
+        // bookmark
+        if filters.iter().filter(|f| **f).count() > 1 {
+            return self.fail_bad_payload();
+        }
+
+        // A limit would leave the sync incomplete
+        if data.list.limit.is_some() && (data.since.is_some() || data.sync) {
+            return self.fail_bad_payload();
+        }
+
+        // The fingerprint of an unfiltered listing is cached for as long as the
+        // database is unchanged, so that it needn't be read again to tell
+        let lists_all =
+            !filters[..4].iter().any(|f| *f) && data.since.is_none() && data.list.limit.is_none();
+        let version = if lists_all {
+            db.data_version().ok()
+        } else {
+            None
         };
 
-        if all_bms.is_empty() {
-            return Ok(gen_res(all_bms, false));
+        let view = listing_view(data, caps);
+
+        if let (Some(version), Some(etag), false) = (version, &data.if_none_match, data.sync) {
+            let unchanged =
+                self.fingerprint.borrow().as_ref().is_some_and(|fp| {
+                    fp.version == version && fp.view == view && fp.value == *etag
+                });
+
+            if unchanged {
+                return json!({
+                    "success": true,
+                    "notModified": true,
+                    "fingerprint": etag,
+                });
+            }
         }
 
-        let offset = bms_offset.unwrap_or(0);
-        let bms = &all_bms[offset..];
+        // Fields beyond the bookmarks to include in every page
+        let mut extra = serde_json::Map::new();
+
+        let bookmarks = if let Some(token) = &data.since {
+            db.changes_since(token).map(|changes| {
+                extra.insert(String::from("deleted"), json!(changes.deleted));
+                extra.insert(String::from("syncToken"), json!(changes.token));
+                changes.bookmarks
+            })
+        } else if let Some(search) = &data.search {
+            db.search_bookmarks(&search.keywords, search.mode, &data.list)
+        } else if let Some(query) = &data.tag_query {
+            match TagQuery::parse(query) {
+                Ok(query) => db.get_bookmarks_by_tags(&query, &data.list),
+                Err(_) => return self.fail_bad_tag_query(),
+            }
+        } else if let Some(host) = &data.host {
+            db.get_bookmarks_by_host(host, data.include_subdomains, &data.list)
+        } else if let Some(prefix) = &data.url_prefix {
+            db.get_bookmarks_by_url_prefix(prefix, &data.list)
+        } else if data.sync {
+            // Issue the token first so that any changes made whilst reading
+            // are included in the next sync
+            db.sync_token().and_then(|token| {
+                extra.insert(String::from("syncToken"), json!(token));
+                db.get_all_bookmarks(&data.list)
+            })
+        } else {
+            db.get_all_bookmarks(&data.list)
+        };
 
-        match max_page_size_bytes {
-            BookmarksSplitPayloadSize::Unlimited => Ok(gen_res(&bms.to_vec(), false)),
-            BookmarksSplitPayloadSize::Limited(max_size) => {
-                let overhead = serde_json::to_vec(&gen_res(&[], false))
-                    .map_err(|_| BookmarksSplitError::Unknown)?
-                    .len();
-                let mut size_so_far = overhead;
+        match bookmarks {
+            Ok(bms) => {
+                // Changes are described by the sync instead
+                if data.since.is_none() {
+                    let value = fingerprint(&bms, view);
+
+                    if let Some(version) = version {
+                        self.fingerprint.replace(Some(Fingerprint {
+                            version,
+                            view,
+                            value: value.clone(),
+                        }));
+                    }
 
-                for (i, bm) in bms.iter().enumerate() {
-                    let bm_size = serde_json::to_vec(&bm)
-                        .map_err(|_| BookmarksSplitError::Unknown)?
-                        .len();
+                    if data.if_none_match.as_ref() == Some(&value) {
+                        let mut res = json!({
+                            "success": true,
+                            "notModified": true,
+                            "fingerprint": value,
+                        });
 
-                    let new_size_so_far = size_so_far + bm_size + std::cmp::min(i, 1); // Comma is 1 byte
-                    if new_size_so_far >= max_size {
-                        if i == 0 {
-                            return Err(BookmarksSplitError::BookmarkLargerThanMaxPayloadSize);
+                        if let Some(res) = res.as_object_mut() {
+                            res.extend(extra);
                         }
 
-                        return Ok(gen_res(&all_bms[offset..offset + i].to_vec(), true));
+                        return res;
                     }
 
-                    let is_last_loop = i == bms.len() - 1;
-                    if is_last_loop {
-                        return Ok(gen_res(&bms.to_vec(), false));
+                    extra.insert(String::from("fingerprint"), json!(value));
+                }
+
+                // Rows that couldn't be read are listed by ID on every page
+                extra.insert(
+                    String::from("skipped"),
+                    json!(bms.skipped.iter().map(|row| row.id).collect::<Vec<_>>()),
+                );
+
+                let bookmarks = bms
+                    .bookmarks
+                    .iter()
+                    .map(|bm| {
+                        self.projected_bookmark_json(bm, caps, data.fields.as_deref())
+                            .to_string()
+                    })
+                    .collect();
+
+                match self.new_listing(bookmarks, extra) {
+                    Ok(listing) => {
+                        let res = self.get_page(&listing, 0);
+
+                        // Supersede the previous listing, only holding on to
+                        // this one if it has more pages to come
+                        self.listing
+                            .replace(Some(listing).filter(|listing| listing.pages.len() > 1));
+
+                        res
                     }
+                    Err(_) => {
+                        self.listing.replace(None);
 
-                    size_so_far = new_size_so_far;
+                        self.fail_generic()
+                    }
                 }
+            }
+            Err(err) => self.fail_db_error(&err),
+        }
+    }
+
+    fn new_listing(
+        &self,
+        bookmarks: Vec<String>,
+        extra: serde_json::Map<String, Json>,
+    ) -> Result<Listing, BookmarksSplitError> {
+        let id = self.listing_id.get().wrapping_add(1);
+        self.listing_id.set(id);
+
+        // Leave room in every page for the extra fields and the longest
+        // possible cursor
+        let extra_size = extra
+            .iter()
+            .map(|(key, value)| r#","":"#.len() + key.len() + value.to_string().len())
+            .sum::<usize>()
+            + r#","cursor":"""#.len()
+            + cursor(id, bookmarks.len()).len();
+
+        let pages = self.paginate(
+            &bookmarks.iter().map(String::len).collect::<Vec<_>>(),
+            BookmarksSplitPayloadSize::Limited(ONE_MEGABYTE_BYTES.saturating_sub(extra_size)),
+        )?;
+
+        Ok(Listing {
+            id,
+            created: Instant::now(),
+            bookmarks,
+            pages,
+            extra,
+        })
+    }
 
-                Err(BookmarksSplitError::Unknown)
+    fn get_next_page(&self, cursor: &str) -> Json {
+        let page = cursor
+            .split_once('.')
+            .and_then(|(id, page)| Some((id.parse::<u64>().ok()?, page.parse::<usize>().ok()?)));
+
+        match (&*self.listing.borrow(), page) {
+            (Some(listing), Some((id, page)))
+                if listing.id == id
+                    && page < listing.pages.len()
+                    && listing.created.elapsed() < LISTING_TTL =>
+            {
+                self.get_page(listing, page)
             }
+            _ => self.fail_cursor_expired(),
         }
     }
 
-    // Route requests per the method
-    pub fn router(&self, payload: Json) -> Json {
-        match &self.db {
-            Ok(db) => match self.method_deserializer(payload.clone()) {
-                Method::Get => serde_json::from_value::<GetRequest>(payload)
-                    .map(|req| self.get(db, &req.data.and_then(|d| d.offset)))
-                    .unwrap_or_else(|_| self.fail_bad_payload()),
-                Method::Options => self.options(),
-                Method::Post => serde_json::from_value::<PostRequest>(payload)
-                    .map(|req| self.post(db, &req.data.bookmarks))
-                    .unwrap_or_else(|_| self.fail_bad_payload()),
-                Method::Put => serde_json::from_value::<PutRequest>(payload)
-                    .map(|req| self.put(db, &req.data.bookmarks))
-                    .unwrap_or_else(|_| self.fail_bad_payload()),
-                Method::Delete => serde_json::from_value::<DeleteRequest>(payload)
-                    .map(|req| self.delete(db, &req.data.bookmark_ids))
-                    .unwrap_or_else(|_| self.fail_bad_payload()),
-                Method::Unknown => self.fail_unknown_method(),
-                Method::None => self.fail_no_method(),
-            },
-            Err(err) => self.fail_init_error(err),
+    /// A page of a listing, with a cursor for the next page if there is one.
+    fn get_page(&self, listing: &Listing, page: usize) -> Json {
+        let are_more = page + 1 < listing.pages.len();
+        // Only this page is parsed back into values, as every response is
+        let bookmarks = format!(
+            "[{}]",
+            listing.bookmarks[listing.pages[page].clone()].join(",")
+        );
+        let mut res = self.page_json::<Json>(&[], are_more);
+
+        match serde_json::from_str(&bookmarks) {
+            Ok(bookmarks) => res["bookmarks"] = bookmarks,
+            Err(_) => return self.fail_generic(),
         }
+
+        if are_more {
+            res["cursor"] = json!(cursor(listing.id, page + 1));
+        }
+
+        if let Some(res) = res.as_object_mut() {
+            res.extend(listing.extra.clone());
+        }
+
+        res
     }
 
-    fn get(&self, db: &T, offset_opt: &Option<usize>) -> Json {
-        let bookmarks = db.get_all_bookmarks();
-        let offset =
-            offset_opt.map_or_else(|| BookmarksSplitOffset::None, BookmarksSplitOffset::Offset);
+    fn bookmark_json(&self, bm: &SavedBookmark, caps: &Capabilities) -> Json {
+        let mut json = json!(bm);
 
-        match bookmarks {
-            Ok(bms) => self
-                .split_bookmarks_subset(
-                    &bms,
-                    offset,
-                    BookmarksSplitPayloadSize::Limited(*ONE_MEGABYTE_BYTES),
-                )
-                .unwrap_or_else(|_| self.fail_generic()),
-            Err(_) => self.fail_generic(),
+        if caps.tags_array {
+            json["tags"] = json!(bm.tags.iter().collect::<Vec<&String>>());
+        }
+
+        json
+    }
+
+    /// Only the requested fields of a bookmark, if any were requested. The ID
+    /// is always included.
+    fn projected_bookmark_json(
+        &self,
+        bm: &SavedBookmark,
+        caps: &Capabilities,
+        fields: Option<&[BookmarkField]>,
+    ) -> Json {
+        let mut json = self.bookmark_json(bm, caps);
+
+        if let (Some(fields), Some(obj)) = (fields, json.as_object_mut()) {
+            obj.retain(|key, _| {
+                key == BookmarkField::Id.name() || fields.iter().any(|f| f.name() == key)
+            });
+        }
+
+        json
+    }
+
+    fn get_lookup(&self, db: &T, data: &RequestDataGetLookup, caps: &Capabilities) -> Json {
+        let matching = if data.normalise {
+            UrlMatch::Normalised(
+                data.ignored_params
+                    .clone()
+                    .unwrap_or_else(|| TRACKING_PARAMS.iter().map(|p| String::from(*p)).collect()),
+            )
+        } else {
+            UrlMatch::Exact
+        };
+
+        match db.get_bookmarks_by_urls(&data.urls, &matching) {
+            Ok(matches) => json!({
+                "success": true,
+                "matches": data
+                    .urls
+                    .iter()
+                    .zip(matches.matches)
+                    .map(|(url, bms)| {
+                        json!({
+                            "url": url,
+                            "bookmarks": bms
+                                .iter()
+                                .map(|bm| {
+                                    self.projected_bookmark_json(bm, caps, data.fields.as_deref())
+                                })
+                                .collect::<Vec<Json>>(),
+                        })
+                    })
+                    .collect::<Vec<Json>>(),
+                "skipped": matches.skipped.iter().map(|row| row.id).collect::<Vec<_>>(),
+            }),
+            Err(err) => self.fail_db_error(&err),
+        }
+    }
+
+    fn get_tags(&self, db: &T) -> Json {
+        match db.get_tags() {
+            Ok(tags) => json!({
+                "success": true,
+                "tags": tags.tags,
+                "skipped": tags.skipped.iter().map(|row| row.id).collect::<Vec<_>>(),
+            }),
+            Err(err) => self.fail_db_error(&err),
+        }
+    }
+
+    fn put_tags(&self, db: &T, from: &[String], to: &str) -> Json {
+        if Tags::parse(to).is_empty() {
+            return self.fail_bad_payload();
+        }
+
+        match db.rename_tags(from, to) {
+            Ok(updated) => json!({
+                "success": true,
+                "updated": updated,
+            }),
+            Err(err) => self.fail_db_error(&err),
+        }
+    }
+
+    fn delete_tags(&self, db: &T, tags: &[String]) -> Json {
+        match db.delete_tags(tags) {
+            Ok(updated) => json!({
+                "success": true,
+                "updated": updated,
+            }),
+            Err(err) => self.fail_db_error(&err),
         }
     }
 
@@ -237,38 +927,118 @@ impl<T: BukuDatabase> Server<T> {
         json!({
             "success": true,
             "binaryVersion": crate_version!(),
+            "capabilities": CAPABILITIES,
         })
     }
 
-    fn post(&self, db: &T, bms: &[UnsavedBookmark]) -> Json {
-        let added = db.add_bookmarks(bms);
-
-        if let Ok(ids) = added {
-            json!({
-                "success": true,
-                "ids": ids,
-            })
-        } else {
-            self.fail_generic()
+    fn post(
+        &self,
+        db: &T,
+        bms: &[UnsavedBookmark],
+        policy: ConflictPolicy,
+        mode: BatchMode,
+        caps: &Capabilities,
+    ) -> Json {
+        match db.add_bookmarks(bms, policy, mode) {
+            // Bookmarks that failed to save are null
+            Ok(added) => json!({
+                "success": added.iter().all(Result::is_ok),
+                "ids": added
+                    .iter()
+                    .map(|res| res.as_ref().ok().map(|added| added.bookmark.id))
+                    .collect::<Vec<_>>(),
+                "bookmarks": added
+                    .iter()
+                    .map(|res| {
+                        res.as_ref()
+                            .ok()
+                            .map(|added| self.bookmark_json(&added.bookmark, caps))
+                    })
+                    .collect::<Vec<_>>(),
+                "results": added
+                    .iter()
+                    .map(|res| match res {
+                        Ok(added) => {
+                            let mut json = self.batch_item_json(Some(added.bookmark.id), None);
+                            if added.existing {
+                                json["existingId"] = json!(added.bookmark.id);
+                            }
+
+                            json
+                        }
+                        Err(err) => self.batch_item_json(None, Some(err)),
+                    })
+                    .collect::<Vec<Json>>(),
+            }),
+            Err(err) => self.fail_db_error(&err),
         }
     }
 
-    fn put(&self, db: &T, bms: &[SavedBookmark]) -> Json {
-        let update = db.update_bookmarks(bms);
+    fn put(&self, db: &T, bms: &[SavedBookmark], mode: BatchMode) -> Json {
+        match db.update_bookmarks(bms, mode) {
+            Ok(updated) => json!({
+                "success": updated.iter().all(Result::is_ok),
+                "results": updated
+                    .iter()
+                    .zip(bms)
+                    .map(|(res, bm)| self.batch_item_json(Some(bm.id), res.as_ref().err()))
+                    .collect::<Vec<Json>>(),
+            }),
+            Err(err) => self.fail_db_error(&err),
+        }
+    }
 
-        json!({ "success": update.is_ok() })
+    fn delete(&self, db: &T, bm_ids: &[BookmarkId], compact: bool, mode: BatchMode) -> Json {
+        match db.delete_bookmarks(bm_ids, compact, mode) {
+            Ok(deleted) => json!({
+                "success": deleted.iter().all(Result::is_ok),
+                "results": deleted
+                    .iter()
+                    .zip(bm_ids)
+                    .map(|(res, id)| self.batch_item_json(Some(*id), res.as_ref().err()))
+                    .collect::<Vec<Json>>(),
+                "moved": compose_id_moves(&deleted),
+            }),
+            Err(err) => self.fail_db_error(&err),
+        }
     }
 
-    fn delete(&self, db: &T, bm_ids: &[BookmarkId]) -> Json {
-        let deletion = db.delete_bookmarks(bm_ids);
+    /// The outcome of a single write within a batch.
+    fn batch_item_json(&self, id: Option<BookmarkId>, err: Option<&DbError>) -> Json {
+        match err {
+            None => json!({
+                "id": id,
+                "success": true,
+            }),
+            Some(err) => {
+                let mut json = json!({
+                    "id": id,
+                    "success": false,
+                    "error": map_db_err_code(err),
+                });
+
+                // Point the WebExtension towards the bookmark it collided with
+                if let DbError::DuplicateUrl(existing) = err {
+                    json["existingId"] = json!(existing);
+                }
 
-        json!({ "success": deletion.is_ok() })
+                json
+            }
+        }
     }
 
     fn fail_generic(&self) -> Json {
         json!({ "success": false })
     }
 
+    fn fail_db_error(&self, err: &DbError) -> Json {
+        json!({
+            "success": false,
+            "message": err.to_string(),
+            "error": map_db_err_code(err),
+        })
+    }
+
     fn fail_no_method(&self) -> Json {
         json!({
             "success": false,
@@ -283,6 +1053,20 @@ impl<T: BukuDatabase> Server<T> {
         })
     }
 
+    fn fail_unknown_resource(&self) -> Json {
+        json!({
+            "success": false,
+            "message": "Unrecognised resource.",
+        })
+    }
+
+    fn fail_unsupported_method(&self) -> Json {
+        json!({
+            "success": false,
+            "message": "Method not supported for resource.",
+        })
+    }
+
     fn fail_bad_payload(&self) -> Json {
         json!({
             "success": false,
@@ -290,20 +1074,48 @@ impl<T: BukuDatabase> Server<T> {
         })
     }
 
-    fn fail_init_error(&self, err: &InitError) -> Json {
+    fn fail_cursor_expired(&self) -> Json {
         json!({
             "success": false,
-            "message": map_init_err_friendly_msg(err),
+            "message": "Cursor has expired.",
+            "error": "cursorExpired",
         })
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::buku::database::{BukuDatabase, DbError, SqliteDatabase};
 
-    fn create_bms(range: impl ExactSizeIterator<Item = u16>) -> Vec<SavedBookmark> {
+    fn fail_offset_unsupported(&self) -> Json {
+        json!({
+            "success": false,
+            "message": "Offsets are no longer supported, use cursors instead.",
+            "error": "offsetUnsupported",
+        })
+    }
+
+    fn fail_bad_tag_query(&self) -> Json {
+        json!({
+            "success": false,
+            "message": "Invalid tag query.",
+        })
+    }
+
+    fn fail_init_error(&self, err: &InitError) -> Json {
+        json!({
+            "success": false,
+            "message": map_init_err_friendly_msg(err),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buku::database::LockConfig;
+    use crate::buku::database::{
+        AddedBookmark, BatchResult, BukuDatabase, DbError, DeletedBookmark, SqliteDatabase,
+        SyncChanges,
+    };
+    use crate::buku::types::{Bookmarks, IdMove, SkippedRow, TagCount, TagCounts, UrlMatches};
+
+    fn create_bms(range: impl ExactSizeIterator<Item = u16>) -> Vec<SavedBookmark> {
         let mut bms = Vec::with_capacity(range.len());
 
         for i in range {
@@ -312,7 +1124,7 @@ mod tests {
                 metadata: String::from(""),
                 desc: String::from(""),
                 url: String::from(""),
-                tags: String::from(""),
+                tags: Tags::default(),
                 flags: 0,
             });
         }
@@ -324,41 +1136,246 @@ mod tests {
         vec![1, 2, 3, 4]
     }
 
+    /// The mock treats this ID as not existing.
+    const MOCK_MISSING_ID: BookmarkId = 404;
+
+    /// The mock treats this URL as already bookmarked with this ID.
+    const MOCK_EXISTING_URL: &str = "https://buku.io";
+    const MOCK_EXISTING_ID: BookmarkId = 7;
+
+    /// Searching the mock for this keyword matches one unreadable row.
+    const MOCK_UNREADABLE_KEYWORD: &str = "unreadable";
+    const MOCK_UNREADABLE_ID: BookmarkId = 13;
+
+    /// Querying the mock for this tag finds the database locked.
+    const MOCK_LOCKED_TAG: &str = "locked";
+
+    /// The mock treats this as the highest ID, to be moved by compaction.
+    const MOCK_LAST_ID: BookmarkId = 100;
+
+    /// The mock issues this sync token, and treats any other as expired.
+    const MOCK_SYNC_TOKEN: &str = "1";
+
+    fn mock_write_id(id: BookmarkId) -> Result<BookmarkId, DbError> {
+        if id == MOCK_MISSING_ID {
+            Err(DbError::NotFound(id))
+        } else {
+            Ok(id)
+        }
+    }
+
     fn create_mocked_server() -> Server<impl BukuDatabase> {
+        create_mocked_server_from(Ok(()))
+    }
+
+    /// A mocked server whose database is created successfully on request.
+    fn create_mocked_server_from(db: Result<(), InitError>) -> Server<impl BukuDatabase> {
         struct BukuMock {}
 
         impl BukuDatabase for BukuMock {
-            fn get_all_bookmarks(&self) -> Result<Vec<SavedBookmark>, DbError> {
-                Ok(Vec::new())
+            fn get_all_bookmarks(&self, _options: &ListOptions) -> Result<Bookmarks, DbError> {
+                Ok(Bookmarks::default())
             }
 
-            fn get_bookmarks_by_id(
+            fn get_bookmarks_by_id(&self, _ids: Vec<BookmarkId>) -> Result<Bookmarks, DbError> {
+                Ok(Bookmarks::default())
+            }
+
+            fn search_bookmarks(
                 &self,
-                _ids: Vec<BookmarkId>,
-            ) -> Result<Vec<SavedBookmark>, DbError> {
-                Ok(Vec::new())
+                keywords: &[String],
+                _mode: SearchMode,
+                _options: &ListOptions,
+            ) -> Result<Bookmarks, DbError> {
+                Ok(keywords
+                    .iter()
+                    .filter(|kw| *kw == MOCK_UNREADABLE_KEYWORD)
+                    .map(|_| {
+                        Err(SkippedRow {
+                            id: Some(MOCK_UNREADABLE_ID),
+                            reason: String::from("Invalid UTF-8"),
+                        })
+                    })
+                    .collect())
             }
 
-            fn add_bookmarks(&self, _bm: &[UnsavedBookmark]) -> Result<Vec<usize>, DbError> {
-                Ok(shared_mock_update_ids())
+            fn get_bookmarks_by_tags(
+                &self,
+                query: &TagQuery,
+                _options: &ListOptions,
+            ) -> Result<Bookmarks, DbError> {
+                match query {
+                    TagQuery::Tag(tag) if tag == MOCK_LOCKED_TAG => Err(DbError::Locked),
+                    _ => Ok(Bookmarks::default()),
+                }
             }
 
-            fn update_bookmarks(&self, _bm: &[SavedBookmark]) -> Result<Vec<usize>, DbError> {
-                Ok(shared_mock_update_ids())
+            fn get_bookmarks_by_host(
+                &self,
+                _host: &str,
+                _include_subdomains: bool,
+                _options: &ListOptions,
+            ) -> Result<Bookmarks, DbError> {
+                Ok(Bookmarks::default())
             }
 
-            fn delete_bookmarks(&self, _bm_ids: &[BookmarkId]) -> Result<Vec<usize>, DbError> {
-                Ok(shared_mock_update_ids())
+            fn get_bookmarks_by_url_prefix(
+                &self,
+                _prefix: &str,
+                _options: &ListOptions,
+            ) -> Result<Bookmarks, DbError> {
+                Ok(Bookmarks::default())
+            }
+
+            fn get_bookmarks_by_urls(
+                &self,
+                urls: &[String],
+                _matching: &UrlMatch,
+            ) -> Result<UrlMatches, DbError> {
+                Ok(UrlMatches {
+                    matches: urls
+                        .iter()
+                        .map(|url| {
+                            create_bms(0..1)
+                                .into_iter()
+                                .filter(|_| url == MOCK_EXISTING_URL)
+                                .map(|bm| SavedBookmark {
+                                    id: MOCK_EXISTING_ID,
+                                    url: url.clone(),
+                                    ..bm
+                                })
+                                .collect()
+                        })
+                        .collect(),
+                    skipped: urls
+                        .iter()
+                        .filter(|url| *url == MOCK_UNREADABLE_KEYWORD)
+                        .map(|_| SkippedRow {
+                            id: Some(MOCK_UNREADABLE_ID),
+                            reason: String::from("Invalid UTF-8"),
+                        })
+                        .collect(),
+                })
+            }
+
+            fn data_version(&self) -> Result<i64, DbError> {
+                Ok(0)
+            }
+
+            fn sync_token(&self) -> Result<String, DbError> {
+                Ok(String::from(MOCK_SYNC_TOKEN))
+            }
+
+            fn changes_since(&self, token: &str) -> Result<SyncChanges, DbError> {
+                if token != MOCK_SYNC_TOKEN {
+                    return Err(DbError::SyncTokenExpired);
+                }
+
+                Ok(SyncChanges {
+                    bookmarks: Bookmarks::default(),
+                    deleted: vec![MOCK_MISSING_ID],
+                    token: String::from(MOCK_SYNC_TOKEN),
+                })
+            }
+
+            fn get_tags(&self) -> Result<TagCounts, DbError> {
+                Ok(TagCounts {
+                    tags: vec![TagCount {
+                        tag: String::from("rust"),
+                        count: 2,
+                    }],
+                    skipped: vec![SkippedRow {
+                        id: Some(MOCK_UNREADABLE_ID),
+                        reason: String::from("Invalid UTF-8"),
+                    }],
+                })
+            }
+
+            fn rename_tags(&self, from: &[String], _to: &str) -> Result<usize, DbError> {
+                Ok(from.len())
             }
-        }
 
-        Server {
-            db: Ok(BukuMock {}),
+            fn delete_tags(&self, tags: &[String]) -> Result<usize, DbError> {
+                Ok(tags.len())
+            }
+
+            fn add_bookmarks(
+                &self,
+                bms: &[UnsavedBookmark],
+                policy: ConflictPolicy,
+                _mode: BatchMode,
+            ) -> BatchResult<AddedBookmark> {
+                Ok(bms
+                    .iter()
+                    .zip(shared_mock_update_ids())
+                    .map(|(bm, id)| {
+                        let existing = bm.url == MOCK_EXISTING_URL;
+                        if existing && policy == ConflictPolicy::Reject {
+                            return Err(DbError::DuplicateUrl(MOCK_EXISTING_ID));
+                        }
+
+                        Ok(AddedBookmark {
+                            bookmark: SavedBookmark {
+                                id: if existing {
+                                    MOCK_EXISTING_ID
+                                } else {
+                                    id as BookmarkId
+                                },
+                                url: bm.url.clone(),
+                                metadata: bm.metadata.clone(),
+                                tags: bm.tags.clone(),
+                                desc: bm.desc.clone(),
+                                flags: bm.flags,
+                            },
+                            existing,
+                        })
+                    })
+                    .collect())
+            }
+
+            fn update_bookmarks(
+                &self,
+                bms: &[SavedBookmark],
+                _mode: BatchMode,
+            ) -> BatchResult<BookmarkId> {
+                Ok(bms.iter().map(|bm| mock_write_id(bm.id)).collect())
+            }
+
+            fn delete_bookmarks(
+                &self,
+                bm_ids: &[BookmarkId],
+                compact: bool,
+                _mode: BatchMode,
+            ) -> BatchResult<DeletedBookmark> {
+                Ok(bm_ids
+                    .iter()
+                    .map(|id| {
+                        mock_write_id(*id).map(|id| DeletedBookmark {
+                            id,
+                            moved: Some(IdMove {
+                                from: MOCK_LAST_ID,
+                                to: id,
+                            })
+                            .filter(|_| compact && id < MOCK_LAST_ID),
+                        })
+                    })
+                    .collect())
+            }
         }
+
+        Server::new(db.map(|_| BukuMock {}), || Ok(BukuMock {}))
     }
 
     fn create_mocked_server_with_init_err(err: InitError) -> Server<SqliteDatabase> {
-        Server { db: Err(err) }
+        Server::new(Err(err), || Err(InitError::FailedToCreateBukuDatabase))
+    }
+
+    /// The fingerprint of these bookmarks as presented per the data of a `GET`
+    /// request without any capabilities.
+    fn view_fingerprint(bms: &Bookmarks, data: Json) -> String {
+        let data = serde_json::from_value::<RequestDataGet>(data).unwrap();
+
+        fingerprint(bms, listing_view(&data, &Capabilities::default()))
     }
 
     fn create_example_saved_bookmarks() -> Vec<SavedBookmark> {
@@ -366,7 +1383,7 @@ mod tests {
             id: 0,
             url: String::from("https://samhh.com"),
             metadata: String::from("title"),
-            tags: String::from(""),
+            tags: Tags::default(),
             desc: String::from("description"),
             flags: 0,
         }]
@@ -376,7 +1393,7 @@ mod tests {
         vec![UnsavedBookmark {
             url: String::from("https://samhh.com"),
             metadata: String::from("title"),
-            tags: String::from(""),
+            tags: Tags::default(),
             desc: String::from("description"),
             flags: 0,
         }]
@@ -387,244 +1404,771 @@ mod tests {
         let server = create_mocked_server();
 
         assert_eq!(
-            server.method_deserializer(json!({ "method": "GET" })),
-            Method::Get
+            server.method_deserializer(json!({ "method": "GET" })),
+            Method::Get
+        );
+
+        assert_eq!(
+            server.method_deserializer(json!({ "method": "get" })),
+            Method::Unknown
+        );
+
+        assert_eq!(server.method_deserializer(json!({})), Method::None);
+
+        assert_eq!(
+            server.method_deserializer(json!({ "other": "property" })),
+            Method::None
+        );
+    }
+
+    #[test]
+    fn test_paginate() {
+        let server = create_mocked_server();
+        let sizes = |range| {
+            create_bms(range)
+                .iter()
+                .map(|bm| serde_json::to_vec(bm).unwrap().len())
+                .collect::<Vec<_>>()
+        };
+        let bm_bytes_length = sizes(0..1)[0];
+        let overhead_bytes_length =
+            serde_json::to_vec(&server.page_json::<SavedBookmark>(&[], false))
+                .unwrap()
+                .len();
+        // Pages fit in strictly less than the limit, with a comma in between
+        let fits_two = BookmarksSplitPayloadSize::Limited(
+            (bm_bytes_length * 2) + 1 + overhead_bytes_length + 1,
+        );
+
+        // No limit
+        assert_eq!(
+            server
+                .paginate(&sizes(0..2), BookmarksSplitPayloadSize::Unlimited)
+                .unwrap(),
+            vec![0..2],
+        );
+
+        // No bookmarks available
+        assert_eq!(
+            server
+                .paginate(
+                    &[],
+                    BookmarksSplitPayloadSize::Limited(overhead_bytes_length)
+                )
+                .unwrap(),
+            vec![0..0],
+        );
+
+        // Insufficient space for both bookmark and overhead
+        assert_eq!(
+            server
+                .paginate(
+                    &sizes(0..1),
+                    BookmarksSplitPayloadSize::Limited(bm_bytes_length + overhead_bytes_length)
+                )
+                .unwrap_err(),
+            BookmarksSplitError::BookmarkLargerThanMaxPayloadSize,
+        );
+
+        // One bookmark, fitting
+        assert_eq!(
+            server
+                .paginate(
+                    &sizes(0..1),
+                    BookmarksSplitPayloadSize::Limited(bm_bytes_length + overhead_bytes_length + 1)
+                )
+                .unwrap(),
+            vec![0..1],
+        );
+
+        // Two bookmarks, both fitting
+        assert_eq!(server.paginate(&sizes(0..2), fits_two).unwrap(), vec![0..2],);
+
+        // Three bookmarks, two fitting with one more available
+        assert_eq!(
+            server.paginate(&sizes(0..3), fits_two).unwrap(),
+            vec![0..2, 2..3],
+        );
+
+        // Five bookmarks, two fitting per page
+        assert_eq!(
+            server.paginate(&sizes(0..5), fits_two).unwrap(),
+            vec![0..2, 2..4, 4..5],
+        );
+
+        // Each page respects the limit
+        let fits_two_and_a_bit =
+            BookmarksSplitPayloadSize::Limited((bm_bytes_length * 3) + 2 + overhead_bytes_length);
+        assert_eq!(
+            server.paginate(&sizes(0..4), fits_two_and_a_bit).unwrap(),
+            vec![0..2, 2..4],
+        );
+    }
+
+    #[test]
+    fn test_bookmark_json() {
+        let server = create_mocked_server();
+        let mut bm = create_bms(0..1).pop().unwrap();
+        bm.tags = Tags::parse("rust,cli");
+
+        assert_eq!(
+            server.bookmark_json(&bm, &Capabilities::default())["tags"],
+            json!(",cli,rust,"),
+        );
+
+        assert_eq!(
+            server.bookmark_json(
+                &bm,
+                &Capabilities {
+                    tags_array: true,
+                    ..Capabilities::default()
+                }
+            )["tags"],
+            json!(["cli", "rust"]),
+        );
+    }
+
+    #[test]
+    fn test_projected_bookmark_json() {
+        let server = create_mocked_server();
+        let bm = create_bms(0..1).pop().unwrap();
+
+        assert_eq!(
+            server.projected_bookmark_json(&bm, &Capabilities::default(), None),
+            server.bookmark_json(&bm, &Capabilities::default()),
+        );
+
+        assert_eq!(
+            server.projected_bookmark_json(
+                &bm,
+                &Capabilities::default(),
+                Some(&[BookmarkField::Url, BookmarkField::Tags])
+            ),
+            json!({ "id": 0, "url": "", "tags": "," }),
+        );
+
+        assert_eq!(
+            server.projected_bookmark_json(&bm, &Capabilities::default(), Some(&[])),
+            json!({ "id": 0 }),
+        );
+    }
+
+    #[test]
+    fn test_router_with_locate_init_error() {
+        let server_failed_locating =
+            create_mocked_server_with_init_err(InitError::FailedToLocateBukuDatabase);
+
+        assert_eq!(
+            server_failed_locating.router(json!({ "method": "GET" })),
+            server_failed_locating.fail_init_error(&InitError::FailedToLocateBukuDatabase),
+        );
+    }
+
+    #[test]
+    fn test_router_with_access_init_error() {
+        let server_failed_locating =
+            create_mocked_server_with_init_err(InitError::FailedToAccessBukuDatabase);
+
+        assert_eq!(
+            server_failed_locating.router(json!({ "method": "GET" })),
+            server_failed_locating.fail_init_error(&InitError::FailedToAccessBukuDatabase),
+        );
+    }
+
+    #[test]
+    fn test_router_post_database() {
+        let server = create_mocked_server_from(Err(InitError::FailedToLocateBukuDatabase));
+
+        assert_eq!(
+            server.router(json!({ "method": "GET" })),
+            server.fail_init_error(&InitError::FailedToLocateBukuDatabase),
+        );
+
+        assert_eq!(
+            server.router(json!({ "method": "POST", "resource": "database" })),
+            json!({ "success": true, "created": true }),
+        );
+
+        assert_eq!(
+            server.router(json!({ "method": "POST", "resource": "database" })),
+            json!({ "success": true, "created": false }),
+        );
+
+        assert_eq!(
+            server.router(json!({ "method": "GET" })),
+            json!({
+                "success": true,
+                "bookmarks": Vec::<SavedBookmark>::new(),
+                "moreAvailable": false,
+                "skipped": [],
+                "fingerprint": view_fingerprint(&Bookmarks::default(), json!({})),
+            }),
+        );
+
+        assert_eq!(
+            server.router(json!({ "method": "GET", "resource": "database" })),
+            server.fail_unsupported_method(),
+        );
+
+        let server_failed_creating =
+            create_mocked_server_with_init_err(InitError::FailedToLocateBukuDatabase);
+
+        assert_eq!(
+            server_failed_creating.router(json!({ "method": "POST", "resource": "database" })),
+            server_failed_creating.fail_init_error(&InitError::FailedToCreateBukuDatabase),
+        );
+
+        let server_failed_accessing =
+            create_mocked_server_with_init_err(InitError::FailedToAccessBukuDatabase);
+
+        assert_eq!(
+            server_failed_accessing.router(json!({ "method": "POST", "resource": "database" })),
+            server_failed_accessing.fail_init_error(&InitError::FailedToAccessBukuDatabase),
+        );
+    }
+
+    #[test]
+    fn test_router_get_cursor() {
+        let server = create_mocked_server();
+        let bookmarks = create_bms(0..20_000)
+            .iter()
+            .map(|bm| json!(bm).to_string())
+            .collect();
+        let listing = server
+            .new_listing(bookmarks, serde_json::Map::new())
+            .unwrap();
+        let mut res = server.get_page(&listing, 0);
+        let (id, pages) = (listing.id, listing.pages.len());
+        server.listing.replace(Some(listing));
+
+        let mut ids = Vec::new();
+        let mut requests = 1;
+
+        loop {
+            ids.extend(
+                res["bookmarks"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|bm| bm["id"].clone()),
+            );
+
+            match res["cursor"].as_str() {
+                Some(next) => {
+                    assert_eq!(res["moreAvailable"], true);
+                    res = server.router(json!({ "method": "GET", "data": { "cursor": next } }));
+                    requests += 1;
+                }
+                None => {
+                    assert_eq!(res["moreAvailable"], false);
+                    break;
+                }
+            }
+        }
+
+        assert!(pages > 1);
+        assert_eq!(requests, pages);
+        assert_eq!(ids, (0..20_000).map(|id| json!(id)).collect::<Vec<_>>());
+
+        // Pages can be requested again
+        assert_eq!(
+            server.router(json!({ "method": "GET", "data": { "cursor": cursor(id, 0) } }))
+                ["cursor"],
+            json!(cursor(id, 1)),
+        );
+
+        for expired in &[
+            cursor(id + 1, 0),
+            cursor(id, pages),
+            id.to_string(),
+            String::from("cursor"),
+        ] {
+            assert_eq!(
+                server.router(json!({ "method": "GET", "data": { "cursor": expired } })),
+                server.fail_cursor_expired(),
+            );
+        }
+
+        server.listing.borrow_mut().as_mut().unwrap().created -= LISTING_TTL;
+        assert_eq!(
+            server.router(json!({ "method": "GET", "data": { "cursor": cursor(id, 1) } })),
+            server.fail_cursor_expired(),
+        );
+        server.listing.borrow_mut().as_mut().unwrap().created += LISTING_TTL;
+
+        // A listing of a single page supersedes the previous listing all the
+        // same
+        assert_eq!(server.router(json!({ "method": "GET" }))["success"], true);
+        assert!(server.listing.borrow().is_none());
+        assert_eq!(
+            server.router(json!({ "method": "GET", "data": { "cursor": cursor(id, 1) } })),
+            server.fail_cursor_expired(),
+        );
+
+        for offset in &[json!(0), json!(250)] {
+            assert_eq!(
+                server.router(json!({ "method": "GET", "data": { "offset": offset } })),
+                server.fail_offset_unsupported(),
+            );
+        }
+    }
+
+    #[test]
+    fn test_router_get() {
+        let server = create_mocked_server();
+
+        assert_eq!(
+            server.router(json!({ "method": "GET" })),
+            json!({
+                "success": true,
+                "bookmarks": Vec::<SavedBookmark>::new(),
+                "moreAvailable": false,
+                "skipped": [],
+                "fingerprint": view_fingerprint(&Bookmarks::default(), json!({})),
+            }),
+        );
+
+        assert_eq!(
+            server.router(json!({ "method": "GET", "data": { "cursor": "1.0" } })),
+            server.fail_cursor_expired(),
+        );
+
+        assert_eq!(
+            server.router(json!({
+                "method": "GET",
+                "data": { "search": { "keywords": ["rust"], "mode": "all" } },
+            })),
+            json!({
+                "success": true,
+                "bookmarks": Vec::<SavedBookmark>::new(),
+                "moreAvailable": false,
+                "skipped": [],
+                "fingerprint": view_fingerprint(&Bookmarks::default(), json!({})),
+            }),
+        );
+
+        assert_eq!(
+            server.router(json!({
+                "method": "GET",
+                "data": { "search": { "keywords": [MOCK_UNREADABLE_KEYWORD] } },
+            })),
+            json!({
+                "success": true,
+                "bookmarks": Vec::<SavedBookmark>::new(),
+                "moreAvailable": false,
+                "skipped": [MOCK_UNREADABLE_ID],
+                "fingerprint": view_fingerprint(
+                    &Bookmarks {
+                    bookmarks: Vec::new(),
+                    skipped: vec![SkippedRow {
+                        id: Some(MOCK_UNREADABLE_ID),
+                        reason: String::from("Invalid UTF-8"),
+                    }],
+                },
+                    json!({}),
+                ),
+            }),
+        );
+
+        assert_eq!(
+            server.router(json!({
+                "method": "GET",
+                "data": { "search": { "keywords": ["rust"], "mode": "some" } },
+            })),
+            server.fail_bad_payload(),
         );
 
         assert_eq!(
-            server.method_deserializer(json!({ "method": "get" })),
-            Method::Unknown
+            server.router(json!({
+                "method": "GET",
+                "data": { "tag_query": "rust + cli - archived" },
+            })),
+            json!({
+                "success": true,
+                "bookmarks": Vec::<SavedBookmark>::new(),
+                "moreAvailable": false,
+                "skipped": [],
+                "fingerprint": view_fingerprint(&Bookmarks::default(), json!({})),
+            }),
         );
 
-        assert_eq!(server.method_deserializer(json!({})), Method::None);
+        assert_eq!(
+            server.router(json!({ "method": "GET", "data": { "tag_query": MOCK_LOCKED_TAG } })),
+            json!({
+                "success": false,
+                "message": "Buku database is locked.",
+                "error": "databaseLocked",
+            }),
+        );
 
         assert_eq!(
-            server.method_deserializer(json!({ "other": "property" })),
-            Method::None
+            server.router(json!({ "method": "GET", "data": { "tag_query": "rust +" } })),
+            server.fail_bad_tag_query(),
         );
-    }
 
-    #[test]
-    fn test_split_bookmarks_subset() {
-        let server = create_mocked_server();
-        let bm_bytes_length = serde_json::to_vec(&create_bms(0..1).pop().unwrap())
-            .unwrap()
-            .to_vec()
-            .len();
-        let overhead_bytes_length = serde_json::to_vec(
-            &server
-                .split_bookmarks_subset(
-                    &create_bms(0..1),
-                    BookmarksSplitOffset::None,
-                    BookmarksSplitPayloadSize::Limited(999_999),
-                )
-                .unwrap(),
-        )
-        .unwrap()
-        .len();
+        assert_eq!(
+            server.router(json!({
+                "method": "GET",
+                "data": { "search": { "keywords": ["rust"] }, "tag_query": "cli" },
+            })),
+            server.fail_bad_payload(),
+        );
 
-        // No limit or offset
         assert_eq!(
-            server
-                .split_bookmarks_subset(
-                    &create_bms(0..2),
-                    BookmarksSplitOffset::None,
-                    BookmarksSplitPayloadSize::Unlimited
-                )
-                .unwrap(),
+            server.router(json!({
+                "method": "GET",
+                "data": { "host": "github.com", "include_subdomains": true },
+            })),
             json!({
                 "success": true,
+                "bookmarks": Vec::<SavedBookmark>::new(),
                 "moreAvailable": false,
-                "bookmarks": create_bms(0..2),
+                "skipped": [],
+                "fingerprint": view_fingerprint(&Bookmarks::default(), json!({})),
             }),
         );
 
-        // No limit with offset
         assert_eq!(
-            server
-                .split_bookmarks_subset(
-                    &create_bms(0..2),
-                    BookmarksSplitOffset::Offset(1),
-                    BookmarksSplitPayloadSize::Unlimited
-                )
-                .unwrap(),
+            server.router(json!({
+                "method": "GET",
+                "data": { "url_prefix": "https://docs.rs/serde/" },
+            })),
             json!({
                 "success": true,
+                "bookmarks": Vec::<SavedBookmark>::new(),
                 "moreAvailable": false,
-                "bookmarks": create_bms(1..2),
+                "skipped": [],
+                "fingerprint": view_fingerprint(&Bookmarks::default(), json!({})),
             }),
         );
 
-        // No bookmarks available
         assert_eq!(
-            server
-                .split_bookmarks_subset(
-                    &Vec::new(),
-                    BookmarksSplitOffset::None,
-                    BookmarksSplitPayloadSize::Limited(overhead_bytes_length)
-                )
-                .unwrap(),
+            server.router(json!({ "method": "GET", "data": { "fields": ["url", "tags"] } })),
             json!({
                 "success": true,
+                "bookmarks": Vec::<SavedBookmark>::new(),
                 "moreAvailable": false,
-                "bookmarks": create_bms(0..0),
+                "skipped": [],
+                "fingerprint": view_fingerprint(&Bookmarks::default(), json!({ "fields": ["url", "tags"] })),
             }),
         );
 
-        // Insufficient space for both bookmark and overhead
         assert_eq!(
-            server
-                .split_bookmarks_subset(
-                    &create_bms(0..1),
-                    BookmarksSplitOffset::None,
-                    BookmarksSplitPayloadSize::Limited(overhead_bytes_length)
-                )
-                .unwrap_err(),
-            BookmarksSplitError::BookmarkLargerThanMaxPayloadSize,
+            server.router(json!({ "method": "GET", "data": { "fields": ["title"] } })),
+            server.fail_bad_payload(),
         );
 
-        // One bookmark, fitting
         assert_eq!(
-            server
-                .split_bookmarks_subset(
-                    &create_bms(0..1),
-                    BookmarksSplitOffset::None,
-                    BookmarksSplitPayloadSize::Limited(bm_bytes_length + overhead_bytes_length)
-                )
-                .unwrap(),
+            server.router(json!({ "method": "GET", "data": { "sync": true } })),
             json!({
                 "success": true,
+                "bookmarks": Vec::<SavedBookmark>::new(),
                 "moreAvailable": false,
-                "bookmarks": create_bms(0..1),
+                "skipped": [],
+                "syncToken": MOCK_SYNC_TOKEN,
+                "fingerprint": view_fingerprint(&Bookmarks::default(), json!({})),
             }),
         );
 
-        // Two bookmarks, both fitting
         assert_eq!(
-            server
-                .split_bookmarks_subset(
-                    &create_bms(0..2),
-                    BookmarksSplitOffset::None,
-                    BookmarksSplitPayloadSize::Limited(
-                        (bm_bytes_length * 2) + overhead_bytes_length
-                    )
-                )
-                .unwrap(),
+            server.router(json!({
+                "method": "GET",
+                "data": { "if_none_match": view_fingerprint(&Bookmarks::default(), json!({})) },
+            })),
             json!({
                 "success": true,
-                "moreAvailable": false,
-                "bookmarks": create_bms(0..2),
+                "notModified": true,
+                "fingerprint": view_fingerprint(&Bookmarks::default(), json!({})),
             }),
         );
 
-        // Three bookmarks, two fitting with one more available
         assert_eq!(
-            server
-                .split_bookmarks_subset(
-                    &create_bms(0..3),
-                    BookmarksSplitOffset::None,
-                    BookmarksSplitPayloadSize::Limited(
-                        (bm_bytes_length * 2) + overhead_bytes_length
-                    )
-                )
-                .unwrap(),
+            server.router(json!({
+                "method": "GET",
+                "data": { "sync": true, "if_none_match": view_fingerprint(&Bookmarks::default(), json!({})) },
+            })),
             json!({
                 "success": true,
-                "moreAvailable": true,
-                "bookmarks": create_bms(0..2),
+                "notModified": true,
+                "fingerprint": view_fingerprint(&Bookmarks::default(), json!({})),
+                "syncToken": MOCK_SYNC_TOKEN,
             }),
         );
 
-        // Three bookmarks, two fitting, one offset so two remaining both fit
+        // The same bookmarks presented differently aren't the same listing
+        for data in &[
+            json!({ "fields": ["url"] }),
+            json!({ "sort": "title" }),
+            json!({ "descending": true }),
+        ] {
+            let mut data = data.clone();
+            data["if_none_match"] = json!(view_fingerprint(&Bookmarks::default(), json!({})));
+
+            assert_eq!(
+                server.router(json!({ "method": "GET", "data": data }))["fingerprint"],
+                json!(view_fingerprint(&Bookmarks::default(), data)),
+            );
+        }
+
         assert_eq!(
-            server
-                .split_bookmarks_subset(
-                    &create_bms(0..3),
-                    BookmarksSplitOffset::Offset(1),
-                    BookmarksSplitPayloadSize::Limited(
-                        (bm_bytes_length * 2) + overhead_bytes_length
-                    )
-                )
-                .unwrap(),
+            server.router(json!({ "method": "GET", "data": { "if_none_match": "0" } })),
             json!({
                 "success": true,
+                "bookmarks": Vec::<SavedBookmark>::new(),
                 "moreAvailable": false,
-                "bookmarks": create_bms(1..3),
+                "skipped": [],
+                "fingerprint": view_fingerprint(&Bookmarks::default(), json!({})),
             }),
         );
 
-        // Four bookmarks, two fitting, one offset so two fit with one more available
         assert_eq!(
-            server
-                .split_bookmarks_subset(
-                    &create_bms(0..4),
-                    BookmarksSplitOffset::Offset(1),
-                    BookmarksSplitPayloadSize::Limited(
-                        (bm_bytes_length * 2) + overhead_bytes_length
-                    )
-                )
-                .unwrap(),
+            server.router(json!({ "method": "GET", "data": { "since": MOCK_SYNC_TOKEN } })),
             json!({
                 "success": true,
-                "moreAvailable": true,
-                "bookmarks": create_bms(1..3),
+                "bookmarks": Vec::<SavedBookmark>::new(),
+                "moreAvailable": false,
+                "skipped": [],
+                "deleted": [MOCK_MISSING_ID],
+                "syncToken": MOCK_SYNC_TOKEN,
             }),
         );
 
-        // Four bookmarks, two fitting, two more available
         assert_eq!(
-            server
-                .split_bookmarks_subset(
-                    &create_bms(0..4),
-                    BookmarksSplitOffset::None,
-                    BookmarksSplitPayloadSize::Limited(
-                        (bm_bytes_length * 2) + overhead_bytes_length
-                    )
-                )
-                .unwrap(),
+            server.router(json!({ "method": "GET", "data": { "since": "0" } })),
+            json!({
+                "success": false,
+                "message": "Sync token has expired.",
+                "error": "syncTokenExpired",
+            }),
+        );
+
+        assert_eq!(
+            server.router(json!({
+                "method": "GET",
+                "data": { "since": MOCK_SYNC_TOKEN, "host": "docs.rs" },
+            })),
+            server.fail_bad_payload(),
+        );
+
+        assert_eq!(
+            server.router(json!({ "method": "GET", "data": { "sync": true, "limit": 10 } })),
+            server.fail_bad_payload(),
+        );
+
+        assert_eq!(
+            server.router(json!({
+                "method": "GET",
+                "data": { "sort": "domain", "descending": true, "limit": 10 },
+            })),
             json!({
                 "success": true,
-                "moreAvailable": true,
-                "bookmarks": create_bms(0..2),
+                "bookmarks": Vec::<SavedBookmark>::new(),
+                "moreAvailable": false,
+                "skipped": [],
+                "fingerprint": view_fingerprint(
+                    &Bookmarks::default(),
+                    json!({ "sort": "domain", "descending": true, "limit": 10 }),
+                ),
             }),
         );
+
+        assert_eq!(
+            server.router(json!({ "method": "GET", "data": { "sort": "colour" } })),
+            server.fail_bad_payload(),
+        );
+
+        assert_eq!(
+            server.router(json!({
+                "method": "GET",
+                "data": { "host": "docs.rs", "url_prefix": "https://docs.rs/serde/" },
+            })),
+            server.fail_bad_payload(),
+        );
     }
 
     #[test]
-    fn test_router_with_locate_init_error() {
-        let server_failed_locating =
-            create_mocked_server_with_init_err(InitError::FailedToLocateBukuDatabase);
+    fn test_router_resources() {
+        let server = create_mocked_server();
 
         assert_eq!(
-            server_failed_locating.router(json!({ "method": "GET" })),
-            server_failed_locating.fail_init_error(&InitError::FailedToLocateBukuDatabase),
+            server.router(json!({ "method": "GET", "resource": "bookmarks" })),
+            server.router(json!({ "method": "GET" })),
+        );
+
+        assert_eq!(
+            server.router(json!({ "method": "GET", "resource": "folders" })),
+            server.fail_unknown_resource(),
+        );
+
+        assert_eq!(
+            server.router(json!({ "method": "POST", "resource": "tags" })),
+            server.fail_unsupported_method(),
         );
     }
 
     #[test]
-    fn test_router_with_access_init_error() {
-        let server_failed_locating =
-            create_mocked_server_with_init_err(InitError::FailedToAccessBukuDatabase);
+    fn test_router_put_tags() {
+        let server = create_mocked_server();
 
         assert_eq!(
-            server_failed_locating.router(json!({ "method": "GET" })),
-            server_failed_locating.fail_init_error(&InitError::FailedToAccessBukuDatabase),
+            server.router(json!({ "method": "PUT", "resource": "tags" })),
+            server.fail_bad_payload(),
+        );
+
+        assert_eq!(
+            server.router(json!({
+                "method": "PUT",
+                "resource": "tags",
+                "data": { "from": ["js", "ecmascript"], "to": " " },
+            })),
+            server.fail_bad_payload(),
+        );
+
+        assert_eq!(
+            server.router(json!({
+                "method": "PUT",
+                "resource": "tags",
+                "data": { "from": ["js", "ecmascript"], "to": "javascript" },
+            })),
+            json!({ "success": true, "updated": 2 }),
         );
     }
 
     #[test]
-    fn test_router_get() {
+    fn test_router_delete_tags() {
         let server = create_mocked_server();
 
         assert_eq!(
-            server.router(json!({ "method": "GET" })),
-            json!({ "success": true, "bookmarks": Vec::<SavedBookmark>::new(), "moreAvailable": false }),
+            server.router(json!({ "method": "DELETE", "resource": "tags", "data": {} })),
+            server.fail_bad_payload(),
+        );
+
+        assert_eq!(
+            server.router(json!({
+                "method": "DELETE",
+                "resource": "tags",
+                "data": { "tags": ["retired"] },
+            })),
+            json!({ "success": true, "updated": 1 }),
+        );
+    }
+
+    #[test]
+    fn test_router_get_tags() {
+        let server = create_mocked_server();
+
+        assert_eq!(
+            server.router(json!({ "method": "GET", "resource": "tags" })),
+            json!({
+                "success": true,
+                "tags": [{ "tag": "rust", "count": 2 }],
+                "skipped": [MOCK_UNREADABLE_ID],
+            }),
+        );
+    }
+
+    #[test]
+    fn test_router_get_lookup() {
+        let server = create_mocked_server();
+
+        assert_eq!(
+            server.router(json!({ "method": "GET", "resource": "lookup" })),
+            server.fail_bad_payload(),
+        );
+
+        assert_eq!(
+            server.router(json!({ "method": "DELETE", "resource": "lookup" })),
+            server.fail_unsupported_method(),
+        );
+
+        assert_eq!(
+            server.router(json!({
+                "method": "GET",
+                "resource": "lookup",
+                "data": { "urls": ["https://samhh.com", MOCK_EXISTING_URL], "normalise": true },
+            })),
+            json!({
+                "success": true,
+                "matches": [
+                    { "url": "https://samhh.com", "bookmarks": [] },
+                    {
+                        "url": MOCK_EXISTING_URL,
+                        "bookmarks": [{
+                            "id": MOCK_EXISTING_ID,
+                            "url": MOCK_EXISTING_URL,
+                            "metadata": "",
+                            "tags": ",",
+                            "desc": "",
+                            "flags": 0,
+                        }],
+                    },
+                ],
+                "skipped": [],
+            }),
+        );
+
+        assert_eq!(
+            server.router(json!({
+                "method": "GET",
+                "resource": "lookup",
+                "data": { "urls": [MOCK_EXISTING_URL], "fields": ["metadata"] },
+            })),
+            json!({
+                "success": true,
+                "matches": [{
+                    "url": MOCK_EXISTING_URL,
+                    "bookmarks": [{ "id": MOCK_EXISTING_ID, "metadata": "" }],
+                }],
+                "skipped": [],
+            }),
+        );
+
+        assert_eq!(
+            server.router(json!({
+                "method": "GET",
+                "resource": "lookup",
+                "data": { "urls": [MOCK_UNREADABLE_KEYWORD] },
+            })),
+            json!({
+                "success": true,
+                "matches": [{ "url": MOCK_UNREADABLE_KEYWORD, "bookmarks": [] }],
+                "skipped": [MOCK_UNREADABLE_ID],
+            }),
+        );
+    }
+
+    #[test]
+    fn test_poll_changes() {
+        let path = std::env::temp_dir().join(format!("bukubrow-poll-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let server = Server::new(
+            SqliteDatabase::create(&path, LockConfig::default())
+                .map_err(|_| InitError::FailedToCreateBukuDatabase),
+            || Err(InitError::FailedToCreateBukuDatabase),
         );
+        let buku = rusqlite::Connection::open(&path).unwrap();
+        let add = |url: &str| {
+            buku.execute("INSERT INTO bookmarks(URL) VALUES (?1);", &[url])
+                .unwrap()
+        };
+
+        // Not yet subscribed
+        add("https://samhh.com");
+        assert_eq!(server.poll_changes(), None);
+
+        server.router(json!({ "method": "GET", "capabilities": ["changeEvents"] }));
+        assert_eq!(server.poll_changes(), None);
 
+        add("https://buku.io");
+        buku.execute("DELETE FROM bookmarks WHERE id = 1;", rusqlite::NO_PARAMS)
+            .unwrap();
         assert_eq!(
-            server.router(json!({ "method": "GET", "data": { "offset": 1 } })),
-            json!({ "success": true, "bookmarks": Vec::<SavedBookmark>::new(), "moreAvailable": false }),
+            server.poll_changes(),
+            Some(json!({ "event": "changed", "added": [2], "updated": [], "deleted": [1] })),
         );
+        assert_eq!(server.poll_changes(), None);
+
+        std::fs::remove_file(&path).unwrap();
     }
 
     #[test]
@@ -633,7 +2177,11 @@ mod tests {
 
         assert_eq!(
             server.router(json!({ "method": "OPTIONS" })),
-            json!({ "success": true, "binaryVersion": crate_version!() })
+            json!({
+                "success": true,
+                "binaryVersion": crate_version!(),
+                "capabilities": ["tagsArray", "changeEvents"],
+            })
         );
     }
 
@@ -655,9 +2203,59 @@ mod tests {
             })),
             json!({
                 "success": true,
-                "ids": shared_mock_update_ids(),
+                "ids": [1],
+                "bookmarks": [{
+                    "id": 1,
+                    "url": "https://samhh.com",
+                    "metadata": "title",
+                    "tags": ",",
+                    "desc": "description",
+                    "flags": 0,
+                }],
+                "results": [{ "id": 1, "success": true }],
             }),
         );
+
+        let mut bms = create_example_unsaved_bookmarks();
+        bms.push(UnsavedBookmark {
+            url: String::from(MOCK_EXISTING_URL),
+            ..create_example_unsaved_bookmarks().remove(0)
+        });
+
+        assert_eq!(
+            server.router(json!({
+                "method": "POST",
+                "data": { "bookmarks": bms, "best_effort": true },
+            }))["results"],
+            json!([
+                { "id": 1, "success": true },
+                {
+                    "id": null,
+                    "success": false,
+                    "error": "duplicateUrl",
+                    "existingId": MOCK_EXISTING_ID,
+                },
+            ]),
+        );
+
+        assert_eq!(
+            server.router(json!({
+                "method": "POST",
+                "data": { "bookmarks": bms, "on_conflict": "mergeTags" },
+            }))["results"],
+            json!([
+                { "id": 1, "success": true },
+                { "id": MOCK_EXISTING_ID, "success": true, "existingId": MOCK_EXISTING_ID },
+            ]),
+        );
+
+        assert_eq!(
+            server.router(json!({
+                "method": "POST",
+                "data": { "bookmarks": bms, "on_conflict": "replace" },
+            })),
+            server.fail_bad_payload(),
+        );
     }
 
     #[test]
@@ -676,7 +2274,7 @@ mod tests {
                     "bookmarks": create_example_saved_bookmarks(),
                 },
             })),
-            json!({ "success": true }),
+            json!({ "success": true, "results": [{ "id": 0, "success": true }] }),
         );
     }
 
@@ -722,7 +2320,44 @@ mod tests {
                     "bookmark_ids": vec![99],
                 },
             })),
-            json!({ "success": true }),
+            json!({
+                "success": true,
+                "results": [{ "id": 99, "success": true }],
+                "moved": [{ "from": MOCK_LAST_ID, "to": 99 }],
+            }),
+        );
+
+        assert_eq!(
+            server.router(json!({
+                "method": "DELETE",
+                "data": {
+                    "bookmark_ids": vec![99],
+                    "preserve_ids": true,
+                },
+            })),
+            json!({
+                "success": true,
+                "results": [{ "id": 99, "success": true }],
+                "moved": [],
+            }),
+        );
+
+        assert_eq!(
+            server.router(json!({
+                "method": "DELETE",
+                "data": {
+                    "bookmark_ids": vec![99, MOCK_MISSING_ID],
+                    "best_effort": true,
+                },
+            })),
+            json!({
+                "success": false,
+                "results": [
+                    { "id": 99, "success": true },
+                    { "id": MOCK_MISSING_ID, "success": false, "error": "notFound" },
+                ],
+                "moved": [{ "from": MOCK_LAST_ID, "to": 99 }],
+            }),
         );
     }
 }